@@ -0,0 +1,226 @@
+//! Blocking client for synchronous programs
+//!
+//! `blocking::LxpApi` offers the methods of the async `LxpApi` as ordinary
+//! functions. It wraps the async client and runs every call to completion on
+//! a private single threaded runtime, so requests, retries and decoding are
+//! the same. Calls from several threads sharing one client are executed one
+//! after the other.
+//!
+//! Do not use it from within an async runtime, use the async client there.
+use crate::lxpaddress::{AddressCheck, AddressWindow};
+use crate::lxpapi;
+use crate::lxpconnection::Connection;
+use crate::lxperror::LxpApiError;
+use crate::lxplimits::Limits;
+use crate::lxpretry::RetryPolicy;
+use crate::lxptariff::{Estimate, Tariff};
+use crate::lxptypes::*;
+
+use chrono::NaiveDate;
+use std::future::Future;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::{Builder, Runtime};
+
+#[derive(Debug, Clone)]
+pub struct LxpApi {
+    api: lxpapi::LxpApi,
+    runtime: Arc<Mutex<Runtime>>,
+}
+
+impl LxpApi {
+    pub fn new(user_name: &str, api_key: &str, url: &str) -> LxpApi {
+        let runtime = Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .expect("Could not create runtime");
+        LxpApi {
+            api: lxpapi::LxpApi::new(user_name, api_key, url),
+            runtime: Arc::new(Mutex::new(runtime)),
+        }
+    }
+
+    /// Replace the default retry policy
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> LxpApi {
+        self.api = self.api.with_retry_policy(retry);
+        self
+    }
+
+    /// Replace the default tariff used for cost estimates
+    pub fn with_tariff(mut self, tariff: Tariff) -> LxpApi {
+        self.api = self.api.with_tariff(tariff);
+        self
+    }
+
+    /// Choose how letters with a problem in the address window are handled
+    pub fn with_address_check(mut self, check: AddressCheck, window: AddressWindow) -> LxpApi {
+        self.api = self.api.with_address_check(check, window);
+        self
+    }
+
+    /// Send all requests to the test environment at `url`
+    pub fn with_sandbox(mut self, url: &str) -> LxpApi {
+        self.api = self.api.with_sandbox(url);
+        self
+    }
+
+    pub fn is_sandbox(&self) -> bool {
+        self.api.is_sandbox()
+    }
+
+    /// Replace the default limits of concurrency, request rate and timeouts
+    pub fn with_limits(mut self, limits: Limits) -> LxpApi {
+        self.api = self.api.with_limits(limits);
+        self
+    }
+
+    pub fn limits(&self) -> &Limits {
+        self.api.limits()
+    }
+
+    /// Connect through a proxy or with additional CA certificates
+    pub fn with_connection(mut self, connection: &Connection) -> Result<LxpApi, LxpApiError> {
+        self.api = self.api.with_connection(connection)?;
+        Ok(self)
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        // A panic in another call does not harm the runtime
+        let mut runtime = match self.runtime.lock() {
+            Ok(runtime) => runtime,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        runtime.block_on(future)
+    }
+
+    pub fn delete_job(&self, id: i32) -> Result<(), LxpApiError> {
+        self.block_on(self.api.delete_job(id))
+    }
+
+    /// Check url, credentials and connection with a single request
+    pub fn check_access(&self) -> Result<AccessCheck, LxpApiError> {
+        self.block_on(self.api.check_access())
+    }
+
+    /// Change the print options of a job on hold or in the queue
+    pub fn update_job(
+        &self,
+        id: i32,
+        color: &ColorPrint,
+        mode: &Mode,
+        ship: &Ship,
+        archived_copy: Option<Vec<u8>>,
+    ) -> Result<JobUpdated, LxpApiError> {
+        self.block_on(self.api.update_job(id, color, mode, ship, archived_copy))
+    }
+
+    pub fn get_balance(&self) -> Result<Balance, LxpApiError> {
+        self.block_on(self.api.get_balance())
+    }
+
+    /// A single job
+    pub fn get_job(&self, id: i32) -> Result<Job, LxpApiError> {
+        self.block_on(self.api.get_job(id))
+    }
+
+    pub fn get_jobs_hold(&self) -> Result<Vec<Job>, LxpApiError> {
+        self.block_on(self.api.get_jobs_hold())
+    }
+
+    pub fn get_jobs_queue(&self, days: i32) -> Result<Vec<Job>, LxpApiError> {
+        self.block_on(self.api.get_jobs_queue(days))
+    }
+
+    pub fn get_jobs_sent(&self, days: i32) -> Result<Vec<Job>, LxpApiError> {
+        self.block_on(self.api.get_jobs_sent(days))
+    }
+
+    /// All invoices, the oldest first
+    pub fn list_invoices(&self) -> Result<Vec<InvoiceSummary>, LxpApiError> {
+        self.block_on(self.api.list_invoices())
+    }
+
+    pub fn get_last_invoice(&self) -> Result<Invoice, LxpApiError> {
+        self.block_on(self.api.get_last_invoice())
+    }
+
+    pub fn get_invoice(&self, id: i32) -> Result<Invoice, LxpApiError> {
+        self.block_on(self.api.get_invoice(id))
+    }
+
+    /// Upload a PDF file as letter, the file name is used as address
+    pub fn set_job(
+        &self,
+        file_name: &str,
+        color: &ColorPrint,
+        mode: &Mode,
+        ship: &Ship,
+        dispatch_date: Option<NaiveDate>,
+    ) -> Result<JobCreated, LxpApiError> {
+        self.block_on(
+            self.api
+                .set_job(file_name, color, mode, ship, dispatch_date),
+        )
+    }
+
+    /// Upload a PDF document read from `reader` as letter
+    pub fn set_job_from_reader<R: Read>(
+        &self,
+        address: &str,
+        mut reader: R,
+        color: &ColorPrint,
+        mode: &Mode,
+        ship: &Ship,
+        dispatch_date: Option<NaiveDate>,
+    ) -> Result<JobCreated, LxpApiError> {
+        let mut pdf_content = Vec::new();
+        reader
+            .read_to_end(&mut pdf_content)
+            .map_err(|source| LxpApiError::PdfFile {
+                path: address.into(),
+                source,
+            })?;
+        self.set_job_from_bytes(address, pdf_content, color, mode, ship, dispatch_date)
+    }
+
+    /// Upload a PDF document held in memory as letter
+    pub fn set_job_from_bytes(
+        &self,
+        address: &str,
+        pdf_content: Vec<u8>,
+        color: &ColorPrint,
+        mode: &Mode,
+        ship: &Ship,
+        dispatch_date: Option<NaiveDate>,
+    ) -> Result<JobCreated, LxpApiError> {
+        self.block_on(
+            self.api
+                .set_job_from_bytes(address, pdf_content, color, mode, ship, dispatch_date),
+        )
+    }
+
+    /// Estimate the cost of a PDF file as letter without uploading it
+    pub fn estimate_job(
+        &self,
+        file_name: &str,
+        color: &ColorPrint,
+        mode: &Mode,
+        ship: &Ship,
+    ) -> Result<Estimate, LxpApiError> {
+        self.api.estimate_job(file_name, color, mode, ship)
+    }
+
+    /// Estimate the cost of a PDF document held in memory
+    pub fn estimate_job_from_bytes(
+        &self,
+        address: &str,
+        pdf_content: &[u8],
+        color: &ColorPrint,
+        mode: &Mode,
+        ship: &Ship,
+    ) -> Result<Estimate, LxpApiError> {
+        self.api
+            .estimate_job_from_bytes(address, pdf_content, color, mode, ship)
+    }
+}
@@ -15,6 +15,42 @@ pub fn cli_definition(app_name: &str, version: &str) -> ArgMatches<'static> {
                 .multiple(true)
                 .help("Be communicative"),
         )
+        // Define flag sandbox, it overrides the profile
+        .arg(
+            Arg::with_name("sandbox")
+                .long("sandbox")
+                .global(true)
+                .help("Use the test environment, letters are neither printed nor charged"),
+        )
+        // Define limits of the web service access, they override the profile
+        .arg(
+            Arg::with_name("concurrency")
+                .long("concurrency")
+                .takes_value(true)
+                .global(true)
+                .help("Number of requests running at the same time (default: 5)"),
+        )
+        .arg(
+            Arg::with_name("requests_per_second")
+                .long("requests_per_second")
+                .takes_value(true)
+                .global(true)
+                .help("Limit of requests started per second (default: no limit)"),
+        )
+        .arg(
+            Arg::with_name("connect_timeout")
+                .long("connect_timeout")
+                .takes_value(true)
+                .global(true)
+                .help("Connect timeout in seconds (default: 10)"),
+        )
+        .arg(
+            Arg::with_name("request_timeout")
+                .long("request_timeout")
+                .takes_value(true)
+                .global(true)
+                .help("Timeout of a request in seconds (default: 120)"),
+        )
         // Define subcommand profile
         .subcommand(
             SubCommand::with_name("profile")
@@ -23,6 +59,11 @@ pub fn cli_definition(app_name: &str, version: &str) -> ArgMatches<'static> {
                     "A profile has a name and contains all information for accessing the web
 service. With the subcommand profile they can be displayed, created and
 deleted. You can also switch between them. 
+
+With --test the access to the web service is checked with the given or the
+active profile: the account, the credit balance and the latency are shown,
+failures are explained. Together with --new the profile is only stored if
+the check succeeds.
 ",
                 )
                 .arg(
@@ -52,6 +93,12 @@ deleted. You can also switch between them.
                         .requires("profile")
                         .help("Switch to profile"),
                 )
+                .arg(
+                    Arg::with_name("test")
+                        .short("t")
+                        .long("test")
+                        .help("Check the access to the web service"),
+                )
                 .arg(
                     Arg::with_name("overview")
                         .short("o")
@@ -92,7 +139,74 @@ deleted. You can also switch between them.
         .subcommand(
             SubCommand::with_name("job")
                 .about("Print job handling")
-                .after_help("Show and delete print jobs.")
+                .after_help("Show, change and delete print jobs.
+
+With --update the print options of a job on hold or in the queue are changed,
+options not given are kept. If the web service can't change a job, it is
+deleted and the copy archived when it was uploaded is uploaded again.
+
+With --track the job lists are polled every --interval seconds until the
+given jobs, or without ids the open jobs of the job history, are sent or
+deleted. Every change of their state is printed. The exit code tells the
+outcome: 0 all jobs sent, 2 a job was deleted, 3 a job is still on hold for
+lack of credit when --timeout passed, 4 a job is still in the queue when
+--timeout passed.")
+                .arg(
+                    Arg::with_name("update")
+                        .short("u")
+                        .long("update")
+                        .takes_value(true)
+                        .value_name("id")
+                        .help("Change the print options of a print job"),
+                )
+                .arg(
+                    Arg::with_name("black_and_white")
+                        .short("b")
+                        .long("black_and_white")
+                        .requires("update")
+                        .conflicts_with("color")
+                        .help("Change to black and white print"),
+                )
+                .arg(
+                    Arg::with_name("color")
+                        .long("color")
+                        .requires("update")
+                        .help("Change to color print"),
+                )
+                .arg(
+                    Arg::with_name("duplex")
+                        .long("duplex")
+                        .requires("update")
+                        .conflicts_with("simplex")
+                        .help("Change to print on both sides"),
+                )
+                .arg(
+                    Arg::with_name("simplex")
+                        .long("simplex")
+                        .requires("update")
+                        .help("Change to print on one side"),
+                )
+                .arg(
+                    Arg::with_name("international")
+                        .long("international")
+                        .requires("update")
+                        .conflicts_with("national")
+                        .help("Change to international destination"),
+                )
+                .arg(
+                    Arg::with_name("national")
+                        .long("national")
+                        .requires("update")
+                        .help("Change to national destination"),
+                )
+                .arg(
+                    Arg::with_name("show")
+                        .short("s")
+                        .long("show")
+                        .takes_value(true)
+                        .value_name("id")
+                        .help("Show all details of a print job"),
+                )
                 .arg(
                     Arg::with_name("delete")
                         .short("d")
@@ -119,17 +233,55 @@ deleted. You can also switch between them.
                         .short("o")
                         .long("overview")
                         .help("Show informations about jobs on remote server"),
-                ),
+                )
+                .arg(
+                    Arg::with_name("track")
+                        .long("track")
+                        .takes_value(true)
+                        .multiple(true)
+                        .min_values(0)
+                        .value_name("id")
+                        .help("Wait until the print jobs are sent (default: open jobs of the history)"),
+                )
+                .args(&track_args()),
         )
         // Define subcommand set
         .subcommand(
             SubCommand::with_name("set")
                 .about("Set print job(s) on server")
-                .after_help("Set a single print job or many print jobs on server")
+                .after_help("Set a single print job or many print jobs on server.
+
+With --estimate the pages are counted locally and the costs are calculated
+with the tariff of the profile and compared with the credit balance. The
+tariff can be adjusted in the profile section of the configuration file.
+
+A PDF file containing several letters is split with one of the --split
+options. With --split_ranges the page ranges are read from a file next to
+the PDF file with the extension .ranges, one letter per line like 1-3.
+Separator pages found by --split_text or --split_blank are not sent.
+
+With --merge several PDF files, e.g. a cover letter and its attachments, are
+sent as one letter. The pages are taken in the order of the files.
+
+Before uploading, the address window of the first page (DIN 5008 form B,
+configurable in the profile) is read and the recipient is shown. An empty
+or overflowing address window is reported as warning, --address_check
+reject keeps such letters from being sent.
+
+Letters already sent are not sent again: the checksum of every letter is
+noted in a ledger of the profile, a letter with the same content is refused
+within duplicate_window_days of the profile (default: 30 days) or while its
+job is on hold or in the queue. A job waiting on the server with the same
+name and page count is a duplicate as well. Equal letters split from other
+pages of the same file are not duplicates. --force sends them anyway.
+
+With --track the letters uploaded are tracked until they are sent, see the
+help of job.")
                 .arg(
                     Arg::with_name("file_or_dir")
                         .required(true)
-                        .help("PDF file or directory with PDF files"),
+                        .multiple(true)
+                        .help("PDF file or directory with PDF files, several PDF files with --merge"),
                 )
                 .arg(
                     Arg::with_name("black_and_white")
@@ -148,7 +300,41 @@ deleted. You can also switch between them.
                         .short("d")
                         .long("duplex")
                         .help("Print on both sides (default: one side)"),
-                ),
+                )
+                .arg(
+                    Arg::with_name("dispatch_date")
+                        .long("dispatch_date")
+                        .takes_value(true)
+                        .value_name("YYYY-MM-DD")
+                        .help("Send the letter(s) not before this day (default: as soon as possible)"),
+                )
+                .arg(
+                    Arg::with_name("estimate")
+                        .short("e")
+                        .long("estimate")
+                        .help("Only estimate the costs, nothing is sent"),
+                )
+                .arg(
+                    Arg::with_name("merge")
+                        .short("m")
+                        .long("merge")
+                        .conflicts_with_all(&["split_pages", "split_ranges", "split_text", "split_blank"])
+                        .help("Send the PDF files in the given order as one letter"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Send letters even if the same content was sent before"),
+                )
+                .arg(
+                    Arg::with_name("track")
+                        .long("track")
+                        .conflicts_with("estimate")
+                        .help("Wait until the uploaded letters are sent"),
+                )
+                .args(&split_args())
+                .args(&track_args())
+                .arg(address_check_arg()),
         )
         // Define subcommand set
         .subcommand(
@@ -160,8 +346,15 @@ after the transfer. The parameters used to print and send the jobs are defined
 in the call.
 
 The profile definitions for access to the print service are expected under 
-/etc/lxp/lxp.toml. A log file is kept which is located in the monitored 
-directory.")
+/etc/lxp/lxp.toml, the environment variable LXP_CONFIG_DIR overrides the 
+directory. A log file is kept which is located in the monitored directory.
+
+With one of the --split options each PDF file is split into several letters,
+see the help of set. The .ranges file must be saved before the PDF file.
+
+A subdirectory moved into the monitored directory is one letter: its PDF
+files are merged in alphabetical order. Move the complete subdirectory, it
+is handled 10 seconds after it appears, and then moved to sent as well.")
                 .arg(
                     Arg::with_name("directory")
                         .required(true)
@@ -184,7 +377,97 @@ directory.")
                         .short("d")
                         .long("duplex")
                         .help("Print on both sides (default: one side)"),
+                )
+                .arg(
+                    Arg::with_name("dispatch_date")
+                        .long("dispatch_date")
+                        .takes_value(true)
+                        .value_name("YYYY-MM-DD")
+                        .help("Send the letters not before this day (default: as soon as possible)"),
+                )
+                .args(&split_args())
+                .arg(address_check_arg()),
+        )
+        // Define subcommand sync
+        .subcommand(
+            SubCommand::with_name("sync")
+                .about("Update the local job history from the server")
+                .after_help("Every uploaded letter is noted in the job history of the profile, the file
+ledger.json in its archive directory: the files it was made of, checksum,
+print options and job id. The server lists sent jobs only for some days,
+sync takes over their status, costs and sent date while they are listed, so
+the history is kept permanently. Jobs uploaded elsewhere are added. A job the
+server no longer knows is marked as deleted if it was seen within the last
+30 days, otherwise its status becomes unknown, it may have been sent long ago.")
+                .arg(
+                    Arg::with_name("days")
+                        .long("days")
+                        .takes_value(true)
+                        .help("Days of sent jobs taken from the server (default: 30)"),
                 ),
         )
         .get_matches()
 }
+
+// Handling of letters failing the address window preflight, used by set
+// and watch-dir
+fn address_check_arg() -> Arg<'static, 'static> {
+    Arg::with_name("address_check")
+        .long("address_check")
+        .takes_value(true)
+        .value_name("CHECK")
+        .possible_values(&["off", "warn", "reject"])
+        .help("Handling of an empty or overflowing address window (default: profile or warn)")
+}
+
+// Polling of the tracked jobs, used by job and set
+fn track_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("interval")
+            .long("interval")
+            .takes_value(true)
+            .value_name("SECONDS")
+            .requires("track")
+            .help("Seconds between two looks at the job lists (default: 60)"),
+        Arg::with_name("timeout")
+            .long("timeout")
+            .takes_value(true)
+            .value_name("SECONDS")
+            .requires("track")
+            .help("Stop tracking after this time (default: until all jobs are sent or deleted)"),
+    ]
+}
+
+// Options to cut PDF files into several letters, used by set and watch-dir
+fn split_args() -> Vec<Arg<'static, 'static>> {
+    let rules = ["split_pages", "split_ranges", "split_text", "split_blank"];
+    let others = |name| {
+        rules
+            .iter()
+            .filter(|rule| **rule != name)
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    vec![
+        Arg::with_name("split_pages")
+            .long("split_pages")
+            .takes_value(true)
+            .value_name("PAGES")
+            .conflicts_with_all(&others("split_pages"))
+            .help("Split each PDF file into letters of this number of pages"),
+        Arg::with_name("split_ranges")
+            .long("split_ranges")
+            .conflicts_with_all(&others("split_ranges"))
+            .help("Split each PDF file along the page ranges in the file <name>.ranges"),
+        Arg::with_name("split_text")
+            .long("split_text")
+            .takes_value(true)
+            .value_name("MARKER")
+            .conflicts_with_all(&others("split_text"))
+            .help("Split each PDF file at pages containing the marker text"),
+        Arg::with_name("split_blank")
+            .long("split_blank")
+            .conflicts_with_all(&others("split_blank"))
+            .help("Split each PDF file at blank pages"),
+    ]
+}
@@ -0,0 +1,39 @@
+//! lxpapi - access the LetterXpress web API
+//!
+//! The library contains everything needed to talk to the LetterXpress web
+//! service: the client `LxpApi`, its error type `LxpApiError` and the
+//! request and response types in `lxptypes`. The command line tool lxp is
+//! built on top of it.
+//!
+//! Build with `default-features = false` to leave out the dependencies of the
+//! command line tool. The feature `blocking` adds a synchronous client in
+//! `blocking` for programs without an async runtime.
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod lxpaddress;
+mod lxpapi;
+mod lxpconnection;
+mod lxpdate;
+mod lxperror;
+mod lxplimits;
+mod lxpmerge;
+mod lxpmoney;
+mod lxppdf;
+mod lxpredact;
+mod lxpretry;
+mod lxpsplit;
+mod lxptariff;
+pub mod lxptypes;
+
+pub use crate::lxpaddress::{read_address, Address, AddressCheck, AddressProblem, AddressWindow};
+pub use crate::lxpapi::{LxpApi, JOB_LOOKUP_DAYS, SANDBOX_URL};
+pub use crate::lxpconnection::Connection;
+pub use crate::lxpdate::validate_dispatch_date;
+pub use crate::lxperror::LxpApiError;
+pub use crate::lxplimits::Limits;
+pub use crate::lxpmerge::{merge_pdfs, MergeError};
+pub use crate::lxpmoney::Money;
+pub use crate::lxppdf::{validate_pdf, PdfError, PdfInfo};
+pub use crate::lxpretry::RetryPolicy;
+pub use crate::lxpsplit::{parse_page_ranges, split_pdf, PdfPart, SplitError, SplitRule};
+pub use crate::lxptariff::{Estimate, Tariff};
@@ -1,10 +1,30 @@
 use chrono::offset::Local;
 use log::{debug, Level, LevelFilter, Metadata, Record};
-use std::{path::PathBuf, io::Write};
+use std::{path::Path, io::Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set as soon as requests go to the sandbox, then every line is marked
+static SANDBOX: AtomicBool = AtomicBool::new(false);
+
+/// Mark all further output as coming from a run against the test environment
+pub fn mark_sandbox() {
+    SANDBOX.store(true, Ordering::Relaxed);
+}
 
 struct AppLogger {
     max_level: LevelFilter,
-    log_file_path: PathBuf,
+    log_file_path: std::path::PathBuf,
+}
+
+// Prefix every line with the sandbox mark, if needed
+fn marked(text: String) -> String {
+    if !SANDBOX.load(Ordering::Relaxed) {
+        return text;
+    }
+    text.split('\n')
+        .map(|line| format!("[TEST] {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 impl log::Log for AppLogger {
@@ -17,14 +37,14 @@ impl log::Log for AppLogger {
 
             // Write to log File
             let mut file = std::fs::OpenOptions::new()
-                .write(true)
                 .create(true)
                 .append(true)
                 .open(&self.log_file_path)
                 .expect("Can't open logfile");
 
+            let text = record.args().to_string();
             let local = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-            let s = format!("{} {} - {}\n", &local, record.level(), record.args());
+            let s = format!("{} {} - {}\n", &local, record.level(), marked(text.clone()));
             if let Err(e) = file.write_all(s.as_bytes()) {
                 eprintln!("Couldn't write to logfile: {}", e);
                 std::process::exit(1);
@@ -33,12 +53,12 @@ impl log::Log for AppLogger {
             // Write to stdio or stderror
             match record.level() {
                 Level::Error => {
-                    eprintln!("{} - {}", record.level(), record.args());
+                    eprintln!("{}", marked(format!("{} - {}", record.level(), text)));
                     eprintln!("aborting...");
                     std::process::exit(1);
                 }
-                Level::Info => println!("{}", record.args()),
-                _ => println!("{} - {}", record.level(), record.args()),
+                Level::Info => println!("{}", marked(text)),
+                _ => println!("{}", marked(format!("{} - {}", record.level(), text))),
             }
         }
     }
@@ -46,11 +66,11 @@ impl log::Log for AppLogger {
     fn flush(&self) {}
 }
 
-pub fn init(app_name: &str, log_dir: &PathBuf, log_level: u64) {
+pub fn init(app_name: &str, log_dir: &Path, log_level: u64) {
     let level = match log_level {
         0 => LevelFilter::Info,
         1 => LevelFilter::Debug,
-        2 | _ => LevelFilter::Trace,
+        _ => LevelFilter::Trace,
     };
 
     let logger = AppLogger {
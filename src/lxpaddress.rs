@@ -0,0 +1,371 @@
+/// Preflight of the address window
+///
+/// The print service puts the first page into a window envelope, so the
+/// recipient must be printed in the address field defined by DIN 5008. Text
+/// outside of it is not visible and the letter is returned or lost. The text
+/// on page 1 is located by following the text positioning operators of the
+/// content stream. Glyph widths are not known without the fonts, so the
+/// width of a text is estimated from its length and font size.
+use crate::lxppdf::PdfError;
+
+use lopdf::content::Content;
+use lopdf::{Document, Object, ObjectId};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const POINTS_PER_MM: f64 = 72.0 / 25.4;
+// Average glyph width in relation to the font size
+const GLYPH_WIDTH: f64 = 0.5;
+// Text may touch the edges of the field by this much
+const TOLERANCE_MM: f64 = 1.0;
+// The address field has room for 3 lines of remarks and 6 address lines
+const MAX_LINES: usize = 9;
+
+/// Address field on the first page, in millimeters from the top left corner
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AddressWindow {
+    pub left: f64,
+    pub top: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl AddressWindow {
+    /// DIN 5008 form A, with a short letterhead
+    pub fn din5008_a() -> AddressWindow {
+        AddressWindow {
+            left: 20.0,
+            top: 27.0,
+            width: 85.0,
+            height: 45.0,
+        }
+    }
+
+    /// DIN 5008 form B, with a long letterhead
+    pub fn din5008_b() -> AddressWindow {
+        AddressWindow {
+            top: 45.0,
+            ..AddressWindow::din5008_a()
+        }
+    }
+}
+
+impl Default for AddressWindow {
+    fn default() -> AddressWindow {
+        AddressWindow::din5008_b()
+    }
+}
+
+/// What happens with letters failing the address check
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressCheck {
+    /// The address window is not checked
+    Off,
+    /// The problem is logged, the letter is sent nevertheless
+    #[default]
+    Warn,
+    /// The letter is not sent
+    Reject,
+}
+
+impl std::str::FromStr for AddressCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AddressCheck, String> {
+        match s {
+            "off" => Ok(AddressCheck::Off),
+            "warn" => Ok(AddressCheck::Warn),
+            "reject" => Ok(AddressCheck::Reject),
+            _ => Err(format!("'{}' is not one of off, warn and reject", s)),
+        }
+    }
+}
+
+/// Problem found in the address window
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddressProblem {
+    /// No text in the address window, maybe it is a scanned letter
+    Empty,
+    /// Text sticks out of the address window
+    Overflow { text: String },
+    /// More lines than the address window has room for
+    TooManyLines { lines: usize },
+}
+
+impl fmt::Display for AddressProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressProblem::Empty => write!(f, "no address found in the address window"),
+            AddressProblem::Overflow { text } => {
+                write!(f, "text '{}' does not fit into the address window", text)
+            }
+            AddressProblem::TooManyLines { lines } => write!(
+                f,
+                "{} lines in the address window, there is room for {}",
+                lines, MAX_LINES
+            ),
+        }
+    }
+}
+
+/// Text found in the address window
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Address {
+    /// Lines from top to bottom, including a return address
+    pub lines: Vec<String>,
+    pub problem: Option<AddressProblem>,
+}
+
+impl Address {
+    /// The lines in one line, for messages
+    pub fn recipient(&self) -> String {
+        self.lines.join(", ")
+    }
+}
+
+// A piece of text shown on the page, in points from the bottom left corner
+#[derive(Debug, Clone)]
+struct Fragment {
+    x: f64,
+    y: f64,
+    size: f64,
+    text: String,
+}
+
+impl Fragment {
+    fn width(&self) -> f64 {
+        self.text.chars().count() as f64 * self.size * GLYPH_WIDTH
+    }
+}
+
+/// Read the text in the address window of the first page
+pub fn read_address(pdf: &[u8], window: &AddressWindow) -> Result<Address, PdfError> {
+    let doc = Document::load_mem(pdf).map_err(|e| PdfError::Unparsable(e.to_string()))?;
+    let page_id = match doc.get_pages().get(&1) {
+        Some(page_id) => *page_id,
+        None => return Err(PdfError::NoPages),
+    };
+    let page_height = page_height(&doc, page_id);
+    let left = window.left * POINTS_PER_MM;
+    let right = (window.left + window.width) * POINTS_PER_MM;
+    let top = page_height - window.top * POINTS_PER_MM;
+    let bottom = page_height - (window.top + window.height) * POINTS_PER_MM;
+    let tolerance = TOLERANCE_MM * POINTS_PER_MM;
+
+    let mut inside: Vec<Fragment> = Vec::new();
+    let mut problem = None;
+    for fragment in text_fragments(&doc, page_id) {
+        if fragment.text.trim().is_empty() || fragment.y < bottom || fragment.y > top {
+            continue;
+        }
+        let end = fragment.x + fragment.width();
+        if end <= left || fragment.x >= right {
+            continue;
+        }
+        if (fragment.x < left - tolerance || end > right + tolerance) && problem.is_none() {
+            problem = Some(AddressProblem::Overflow {
+                text: fragment.text.trim().to_string(),
+            });
+        }
+        inside.push(fragment);
+    }
+
+    let lines = lines(inside);
+    if problem.is_none() {
+        if lines.is_empty() {
+            problem = Some(AddressProblem::Empty);
+        } else if lines.len() > MAX_LINES {
+            problem = Some(AddressProblem::TooManyLines { lines: lines.len() });
+        }
+    }
+    Ok(Address { lines, problem })
+}
+
+// Join fragments on the same baseline, from top to bottom and left to right
+fn lines(mut fragments: Vec<Fragment>) -> Vec<String> {
+    fragments.sort_by(|a, b| {
+        b.y.partial_cmp(&a.y)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.x.partial_cmp(&b.x).unwrap_or(std::cmp::Ordering::Equal))
+    });
+    let mut lines: Vec<(f64, f64, String)> = Vec::new();
+    for fragment in fragments {
+        match lines.last_mut() {
+            Some((y, end, text)) if (*y - fragment.y).abs() < fragment.size * 0.3 => {
+                // Glyphs and words may be shown one by one
+                if fragment.x - *end > fragment.size * 0.15 && !text.ends_with(' ') {
+                    text.push(' ');
+                }
+                text.push_str(&fragment.text);
+                *end = fragment.x + fragment.width();
+            }
+            _ => lines.push((fragment.y, fragment.x + fragment.width(), fragment.text.clone())),
+        }
+    }
+    lines
+        .into_iter()
+        .map(|(_, _, text)| text.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|text| !text.is_empty())
+        .collect()
+}
+
+// Height of the page, A4 if the document does not tell
+fn page_height(doc: &Document, page_id: ObjectId) -> f64 {
+    let mut id = Some(page_id);
+    // Malformed documents may contain cycles
+    for _ in 0..32 {
+        let node_id = match id {
+            Some(node_id) => node_id,
+            None => break,
+        };
+        let node = match doc.get_dictionary(node_id) {
+            Ok(node) => node,
+            Err(_) => break,
+        };
+        if let Ok(media_box) = node.get(b"MediaBox").and_then(Object::as_array) {
+            let values: Vec<f64> = media_box.iter().filter_map(number).collect();
+            if values.len() == 4 {
+                return (values[3] - values[1]).abs();
+            }
+        }
+        id = node.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+    297.0 * POINTS_PER_MM
+}
+
+fn number(object: &Object) -> Option<f64> {
+    match object {
+        Object::Integer(i) => Some(*i as f64),
+        Object::Real(r) => Some(*r),
+        _ => None,
+    }
+}
+
+// Affine transformation [a b c d e f] as used by PDF
+type Matrix = [f64; 6];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+fn multiply(m: &Matrix, n: &Matrix) -> Matrix {
+    [
+        m[0] * n[0] + m[1] * n[2],
+        m[0] * n[1] + m[1] * n[3],
+        m[2] * n[0] + m[3] * n[2],
+        m[2] * n[1] + m[3] * n[3],
+        m[4] * n[0] + m[5] * n[2] + n[4],
+        m[4] * n[1] + m[5] * n[3] + n[5],
+    ]
+}
+
+fn translate(tx: f64, ty: f64) -> Matrix {
+    [1.0, 0.0, 0.0, 1.0, tx, ty]
+}
+
+// Shown strings, byte by byte; good enough for the usual Latin encodings
+fn shown_text(operands: &[Object]) -> String {
+    let mut text = String::new();
+    for operand in operands {
+        match operand {
+            Object::String(bytes, _) => text.extend(bytes.iter().map(|b| *b as char)),
+            Object::Array(items) => text.push_str(&shown_text(items)),
+            _ => (),
+        }
+    }
+    text
+}
+
+// Follow the graphics and text state of a page to place its texts
+fn text_fragments(doc: &Document, page_id: ObjectId) -> Vec<Fragment> {
+    let operations = doc
+        .get_page_content(page_id)
+        .ok()
+        .and_then(|content| Content::decode(&content).ok())
+        .map(|content| content.operations)
+        .unwrap_or_default();
+
+    let mut fragments = Vec::new();
+    let mut ctm = IDENTITY;
+    let mut saved = Vec::new();
+    let mut text_matrix = IDENTITY;
+    let mut line_matrix = IDENTITY;
+    let mut font_size = 0.0;
+    let mut leading = 0.0;
+    for operation in operations {
+        let operands: Vec<f64> = operation.operands.iter().filter_map(number).collect();
+        let next_line = |line_matrix: &mut Matrix, text_matrix: &mut Matrix, tx, ty| {
+            *line_matrix = multiply(&translate(tx, ty), line_matrix);
+            *text_matrix = *line_matrix;
+        };
+        let mut show = |text: String, text_matrix: &Matrix| {
+            let m = multiply(text_matrix, &ctm);
+            let scale = (m[0] * m[3] - m[1] * m[2]).abs().sqrt();
+            fragments.push(Fragment {
+                x: m[4],
+                y: m[5],
+                size: font_size * scale,
+                text,
+            });
+        };
+        match (operation.operator.as_str(), operands.as_slice()) {
+            ("q", _) => saved.push(ctm),
+            ("Q", _) => ctm = saved.pop().unwrap_or(IDENTITY),
+            ("cm", [a, b, c, d, e, f]) => ctm = multiply(&[*a, *b, *c, *d, *e, *f], &ctm),
+            ("BT", _) => {
+                text_matrix = IDENTITY;
+                line_matrix = IDENTITY;
+            }
+            ("Tf", [size]) => font_size = *size,
+            ("TL", [tl]) => leading = *tl,
+            ("Td", [tx, ty]) => next_line(&mut line_matrix, &mut text_matrix, *tx, *ty),
+            ("TD", [tx, ty]) => {
+                leading = -ty;
+                next_line(&mut line_matrix, &mut text_matrix, *tx, *ty);
+            }
+            ("Tm", [a, b, c, d, e, f]) => {
+                text_matrix = [*a, *b, *c, *d, *e, *f];
+                line_matrix = text_matrix;
+            }
+            ("T*", _) => next_line(&mut line_matrix, &mut text_matrix, 0.0, -leading),
+            ("Tj", _) | ("TJ", _) => show(shown_text(&operation.operands), &text_matrix),
+            ("'", _) | ("\"", _) => {
+                next_line(&mut line_matrix, &mut text_matrix, 0.0, -leading);
+                show(shown_text(&operation.operands), &text_matrix);
+            }
+            _ => (),
+        }
+    }
+    fragments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fragment(x: f64, y: f64, text: &str) -> Fragment {
+        Fragment {
+            x,
+            y,
+            size: 10.0,
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn fragments_are_joined_into_lines() {
+        let lines = lines(vec![
+            fragment(100.0, 600.0, "Berlin"),
+            fragment(60.0, 600.0, "12345"),
+            fragment(60.0, 612.0, "M"),
+            fragment(65.0, 612.0, "ax"),
+            fragment(85.0, 612.0, "Mustermann"),
+        ]);
+        assert_eq!(lines, vec!["Max Mustermann", "12345 Berlin"]);
+    }
+
+    #[test]
+    fn matrices() {
+        let m = multiply(&translate(10.0, 20.0), &[2.0, 0.0, 0.0, 2.0, 5.0, 5.0]);
+        assert_eq!(m, [2.0, 0.0, 0.0, 2.0, 25.0, 45.0]);
+    }
+}
@@ -17,40 +17,46 @@ extern crate md5;
 extern crate reqwest;
 extern crate serde_json;
 
+use crate::lxpaddress::{read_address, AddressCheck, AddressWindow};
+use crate::lxpconnection::{build_client, ClientConfig, Connection};
+use crate::lxpdate::validate_dispatch_date;
+use crate::lxperror::LxpApiError;
+use crate::lxplimits::{Limits, Throttle};
+use crate::lxppdf::validate_pdf;
+use crate::lxpredact::{for_log, text_for_log};
+use crate::lxpretry::RetryPolicy;
+use crate::lxptariff::{Estimate, Tariff};
 use crate::lxptypes::*;
 
+use chrono::NaiveDate;
 use log::*;
-use std::fmt;
-use std::io::Read;
+use reqwest::Method;
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Days of the queue and sent lists searched for a single job if the server
+/// has no job endpoint
+pub const JOB_LOOKUP_DAYS: i32 = 30;
+
+/// Test environment of LetterXpress, letters are neither printed nor charged
+pub const SANDBOX_URL: &str = "https://sandbox.letterxpress.de/v1/";
 
 #[derive(Debug, Clone)]
 pub struct LxpApi {
     url: String,
+    sandbox: bool,
     auth: SubNameAndKey,
     client: reqwest::Client,
-}
-
-pub enum LxpApiError {
-    PdfFileError,
-    RestError,
-    JsonError,
-}
-
-// user-facing output
-impl fmt::Display for LxpApiError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            LxpApiError::PdfFileError => write!(f, "No PDF ile or file reading error"), 
-            LxpApiError::RestError => write!(f, "Web service: check url, user and apikey"),
-            LxpApiError::JsonError => write!(f, "Internal JSON error, please inform the developers"),
-        }
-    }
-}
-
-impl fmt::Debug for LxpApiError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{{ file: {}, line: {} }}", file!(), line!()) // programmer-facing output
-    }
+    retry: RetryPolicy,
+    tariff: Tariff,
+    address_check: AddressCheck,
+    address_window: AddressWindow,
+    limits: Limits,
+    throttle: Throttle,
+    client_config: ClientConfig,
+    // Currency of the account, known after the first balance request
+    currency: Arc<Mutex<Option<String>>>,
 }
 
 impl LxpApi {
@@ -59,162 +65,569 @@ impl LxpApi {
             username: user_name.into(),
             apikey: api_key.into(),
         };
-        let client = reqwest::Client::new();
+        let limits = Limits::default();
+        let client_config = ClientConfig::default();
         LxpApi {
             url: url.into(),
-            auth: auth,
-            client: client,
+            sandbox: false,
+            auth,
+            client: build_client(&limits, &client_config),
+            retry: RetryPolicy::default(),
+            tariff: Tariff::default(),
+            address_check: AddressCheck::default(),
+            address_window: AddressWindow::default(),
+            throttle: Throttle::new(&limits),
+            limits,
+            client_config,
+            currency: Default::default(),
         }
     }
 
-    pub async fn delete_job(&self, id: i32) -> Result<Response, LxpApiError> {
-        let sub_url = format!("deleteJob/{}", id);
-        let mut body = RequestLetter::default();
-        body.auth = self.auth.clone();
-        self.delete(&sub_url, &body).await
+    /// Send all requests to the test environment at `url`, usually
+    /// `SANDBOX_URL`, instead of the url given to `new`
+    pub fn with_sandbox(mut self, url: &str) -> LxpApi {
+        self.url = url.into();
+        self.sandbox = true;
+        self
     }
 
-    pub async fn get_blance(&self) -> Result<Response, LxpApiError> {
-        let mut body = RequestLetter::default();
-        body.auth = self.auth.clone();
-        self.get("getBalance", &body).await
+    /// True if the requests go to the test environment
+    pub fn is_sandbox(&self) -> bool {
+        self.sandbox
     }
 
-    pub async fn get_jobs_hold(&self) -> Result<Response, LxpApiError> {
-        let mut body = RequestLetter::default();
-        body.auth = self.auth.clone();
-        self.get("getJobs/hold", &body).await
+    /// Replace the default limits of concurrency, request rate and timeouts
+    pub fn with_limits(mut self, limits: Limits) -> LxpApi {
+        self.client = build_client(&limits, &self.client_config);
+        self.throttle = Throttle::new(&limits);
+        self.limits = limits;
+        self
     }
 
-    pub async fn get_jobs_queue(&self, days: i32) -> Result<Response, LxpApiError> {
-        let mut body = RequestLetter::default();
-        body.auth = self.auth.clone();
-        let sub_url = format!("getJobs/queue/{}", days);
-        self.get(&sub_url, &body).await
+    pub fn limits(&self) -> &Limits {
+        &self.limits
     }
 
-    pub async fn get_jobs_sent(&self, days: i32) -> Result<Response, LxpApiError> {
-        let mut body = RequestLetter::default();
-        body.auth = self.auth.clone();
-        let sub_url = format!("getJobs/sent/{}", days);
-        self.get(&sub_url, &body).await
+    /// Connect through a proxy or with additional CA certificates, fails if
+    /// the proxy url or a certificate is not usable
+    pub fn with_connection(mut self, connection: &Connection) -> Result<LxpApi, LxpApiError> {
+        self.client_config = ClientConfig::new(connection)?;
+        self.client = build_client(&self.limits, &self.client_config);
+        Ok(self)
     }
 
-    pub async fn list_invoices(&self) -> Result<Response, LxpApiError> {
-        let mut body = RequestLetter::default();
-        body.auth = self.auth.clone();
-        self.get("listInvoices", &body).await
+    /// Replace the default retry policy
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> LxpApi {
+        self.retry = retry;
+        self
     }
 
-    pub async fn get_last_invoice(&self) -> Result<(Response, Vec<u8>), LxpApiError> {
-        let mut body = RequestLetter::default();
-        body.auth = self.auth.clone();
-        let r: Response = self.get("getInvoice", &body).await?;
-        match &r.invoice {
-            Some(invoice) => {
-                let pdf_base64_data = invoice.pdf_data.clone().unwrap();
-                let pdf_data = base64::decode(pdf_base64_data).unwrap();
-                return Ok((r, pdf_data));
+    /// Replace the default tariff used for cost estimates
+    pub fn with_tariff(mut self, tariff: Tariff) -> LxpApi {
+        self.tariff = tariff;
+        self
+    }
+
+    /// Choose how letters are handled whose address window is empty or
+    /// overflows, and where the window is; by default a problem in a DIN 5008
+    /// form B window is logged as warning
+    pub fn with_address_check(mut self, check: AddressCheck, window: AddressWindow) -> LxpApi {
+        self.address_check = check;
+        self.address_window = window;
+        self
+    }
+
+    pub fn address_window(&self) -> &AddressWindow {
+        &self.address_window
+    }
+
+    // Recipient in the address window of the first page; depending on the
+    // address check a problem is logged or rejects the letter
+    fn check_address(&self, address: &str, pdf_content: &[u8]) -> Result<Vec<String>, LxpApiError> {
+        if self.address_check == AddressCheck::Off {
+            return Ok(Vec::new());
+        }
+        let found = read_address(pdf_content, &self.address_window).map_err(|reason| {
+            LxpApiError::InvalidPdf {
+                name: address.into(),
+                reason,
+            }
+        })?;
+        match found.problem {
+            Some(problem) if self.address_check == AddressCheck::Reject => {
+                Err(LxpApiError::Address {
+                    name: address.into(),
+                    problem,
+                })
             }
-            None => return Ok((r, Vec::new())),
+            Some(problem) => {
+                warn!("Letter '{}': {}", address, problem);
+                Ok(found.lines)
+            }
+            None => Ok(found.lines),
         }
     }
 
-    pub async fn get_invoice(&self, id: i32) -> Result<(Response, Vec<u8>), LxpApiError> {
-        let mut body = RequestLetter::default();
-        body.auth = self.auth.clone();
-        let sub_url = format!("getInvoice/{}", id);
-        let r: Response = self.get(&sub_url, &body).await?;
-        match &r.invoice {
-            Some(invoice) => {
-                let pdf_base64_data = invoice.pdf_data.clone().unwrap();
-                let pdf_data = base64::decode(pdf_base64_data).unwrap();
-                return Ok((r, pdf_data));
+    // Request body containing only the authentication
+    fn request(&self) -> RequestLetter {
+        RequestLetter {
+            auth: self.auth.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub async fn delete_job(&self, id: i32) -> Result<(), LxpApiError> {
+        let sub_url = format!("deleteJob/{}", id);
+        self.delete(&sub_url, &self.request()).await?;
+        Ok(())
+    }
+
+    /// Check url, credentials and connection with a single request, without
+    /// retries so that problems show up immediately
+    pub async fn check_access(&self) -> Result<AccessCheck, LxpApiError> {
+        let start = std::time::Instant::now();
+        let r = self.send(Method::GET, "getBalance", &self.request()).await?;
+        let latency = start.elapsed();
+        let balance = match &r.balance {
+            Some(balance) => self.note_currency(decode(Balance::try_from(balance))?),
+            None => return Err(LxpApiError::invalid("balance missing in answer")),
+        };
+        Ok(AccessCheck {
+            account_id: r.auth.as_ref().map(|auth| auth.id.clone()),
+            user: r.auth.as_ref().map(|auth| auth.user.clone()),
+            account_status: r.auth.as_ref().map(|auth| auth.status.clone()),
+            balance: balance.value,
+            latency,
+        })
+    }
+
+    /// Change the print options of a job on hold or in the queue. If the web
+    /// service can't update jobs, the job is deleted and `archived_copy`, the
+    /// document uploaded before, is uploaded again with the new options.
+    pub async fn update_job(
+        &self,
+        id: i32,
+        color: &ColorPrint,
+        mode: &Mode,
+        ship: &Ship,
+        archived_copy: Option<Vec<u8>>,
+    ) -> Result<JobUpdated, LxpApiError> {
+        let job = self.get_job(id).await?;
+        if job.status != JobStatus::Hold && job.status != JobStatus::Queue {
+            return Err(LxpApiError::Conflict {
+                message: format!("job {} is {}, it can't be changed", id, job.status.as_str()),
+            });
+        }
+
+        let mut body = self.request();
+        body.letter.specification.color = color.code();
+        body.letter.specification.mode = mode.as_str().into();
+        body.letter.specification.ship = ship.as_str().into();
+        let sub_url = format!("updateJob/{}", id);
+        match self.put(&sub_url, &body).await {
+            Ok(_) => Ok(JobUpdated {
+                id,
+                resubmitted: false,
+            }),
+            // Only a missing endpoint is worked around, a server in trouble
+            // must not cost the job its id
+            Err(e) if matches!(e.status(), Some(404) | Some(405) | Some(501)) => {
+                debug!("No update endpoint ({}), resubmitting job {}", e, id);
+                match archived_copy {
+                    Some(pdf_content) => {
+                        self.resubmit_job(&job, pdf_content, color, mode, ship).await
+                    }
+                    None => Err(LxpApiError::Unsupported {
+                        message: format!(
+                            "updating jobs, and there is no copy of job {} to upload again",
+                            id
+                        ),
+                    }),
+                }
             }
-            None => return Ok((r, Vec::new())),
+            Err(e) => Err(e),
         }
     }
 
-    pub async fn set_job(
+    // Replace a job by a new one. The copy must fit the job, and it is only
+    // uploaded if the old job is deleted, so the letter is never sent twice.
+    async fn resubmit_job(
         &self,
-        file_name: &str,
+        job: &Job,
+        pdf_content: Vec<u8>,
         color: &ColorPrint,
         mode: &Mode,
         ship: &Ship,
-    ) -> Result<Response, LxpApiError> {
-        let mut letter = SubLetterData::default();
-        match color {
-            ColorPrint::Color => letter.specification.color = 4,
-            ColorPrint::BlackAndWhite => letter.specification.color = 1,
+    ) -> Result<JobUpdated, LxpApiError> {
+        let info = validate_pdf(&pdf_content).map_err(|reason| LxpApiError::InvalidPdf {
+            name: job.address.clone(),
+            reason,
+        })?;
+        if info.pages != job.pages {
+            return Err(LxpApiError::Conflict {
+                message: format!(
+                    "copy of job {} has {} page(s), but the job has {}",
+                    job.id, info.pages, job.pages
+                ),
+            });
         }
-        match mode {
-            Mode::Simplex => letter.specification.mode = "simplex".into(),
-            Mode::Duplex => letter.specification.mode = "duplex".into(),
+
+        // A copy the upload would refuse must not cost the job
+        let recipient = self.check_address(&job.address, &pdf_content)?;
+
+        self.delete_job(job.id).await?;
+        // A dispatch date that has come meanwhile is dropped
+        let dispatch_date = job.dispatchdate.filter(|date| match validate_dispatch_date(*date) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("{}, job {} is sent without it", e, job.id);
+                false
+            }
+        });
+        match self
+            .post_letter(&job.address, pdf_content, job.pages, color, mode, ship, dispatch_date, recipient)
+            .await
+        {
+            Ok(created) => Ok(JobUpdated {
+                id: created.id,
+                resubmitted: true,
+            }),
+            Err(e) => Err(LxpApiError::Resubmit {
+                id: job.id,
+                source: Box::new(e),
+            }),
         }
-        match ship {
-            Ship::International => letter.specification.ship = "international".into(),
-            Ship::National => letter.specification.ship = "national".into(),
+    }
+
+    pub async fn get_balance(&self) -> Result<Balance, LxpApiError> {
+        let r = self.get("getBalance", &self.request()).await?;
+        match &r.balance {
+            Some(balance) => decode(Balance::try_from(balance)).map(|balance| self.note_currency(balance)),
+            None => Err(LxpApiError::invalid("no balance in answer")),
         }
+    }
 
-        if !file_name.to_lowercase().ends_with(".pdf") {
-            trace!("No PDF file - ignored {}", &file_name);
-            return Err(LxpApiError::PdfFileError);
-        };
+    fn note_currency(&self, balance: Balance) -> Balance {
+        *self.currency.lock().unwrap() = Some(balance.value.currency().into());
+        balance
+    }
 
-        let path = std::path::Path::new(&file_name);
-        let mut pdf_file = match std::fs::File::open(&path) {
-            Err(why) => {
-                error!("couldn't open {}", why);
-                return Err(LxpApiError::PdfFileError);
+    // The web service names the currency only with the balance, the amounts
+    // of jobs and invoices are in the same currency
+    async fn currency(&self) -> Result<String, LxpApiError> {
+        let known = self.currency.lock().unwrap().clone();
+        match known {
+            Some(currency) => Ok(currency),
+            None => Ok(self.get_balance().await?.value.currency().into()),
+        }
+    }
+
+    /// A single job. Servers without the job endpoint are handled by looking
+    /// the job up in the job lists.
+    pub async fn get_job(&self, id: i32) -> Result<Job, LxpApiError> {
+        let sub_url = format!("getJob/{}", id);
+        match self.get(&sub_url, &self.request()).await {
+            Ok(r) => match &r.job {
+                Some(job) => decode(Job::try_from((job, self.currency().await?.as_str()))),
+                None => Err(LxpApiError::invalid("job missing in answer")),
             },
-            Ok(file) => file,
-        };
-        letter.address = path.file_name().unwrap().to_str().unwrap().to_string();
+            Err(LxpApiError::NotFound { .. }) | Err(LxpApiError::Http { .. }) => {
+                debug!("Job endpoint failed, looking up job {} in the job lists", id);
+                self.find_job(id).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn find_job(&self, id: i32) -> Result<Job, LxpApiError> {
+        let mut jobs = self.get_jobs_hold().await?;
+        jobs.append(&mut self.get_jobs_queue(JOB_LOOKUP_DAYS).await?);
+        jobs.append(&mut self.get_jobs_sent(JOB_LOOKUP_DAYS).await?);
+        jobs.into_iter()
+            .find(|job| job.id == id)
+            .ok_or_else(|| LxpApiError::NotFound {
+                message: format!("job {} is not in the job lists", id),
+            })
+    }
+
+    pub async fn get_jobs_hold(&self) -> Result<Vec<Job>, LxpApiError> {
+        let r = self.get("getJobs/hold", &self.request()).await?;
+        decode_jobs(&r, &self.currency().await?)
+    }
+
+    pub async fn get_jobs_queue(&self, days: i32) -> Result<Vec<Job>, LxpApiError> {
+        let sub_url = format!("getJobs/queue/{}", days);
+        let r = self.get(&sub_url, &self.request()).await?;
+        decode_jobs(&r, &self.currency().await?)
+    }
+
+    pub async fn get_jobs_sent(&self, days: i32) -> Result<Vec<Job>, LxpApiError> {
+        let sub_url = format!("getJobs/sent/{}", days);
+        let r = self.get(&sub_url, &self.request()).await?;
+        decode_jobs(&r, &self.currency().await?)
+    }
+
+    pub async fn list_invoices(&self) -> Result<Vec<InvoiceSummary>, LxpApiError> {
+        let r = self.get("listInvoices", &self.request()).await?;
+        let currency = self.currency().await?;
+        let mut invoices = Vec::new();
+        if let Some(sub_invoices) = &r.invoices {
+            for invoice in sub_invoices.values() {
+                invoices.push(decode(InvoiceSummary::try_from((invoice, currency.as_str())))?);
+            }
+        }
+        invoices.sort_by_key(|invoice| (invoice.date, invoice.id));
+        Ok(invoices)
+    }
 
+    pub async fn get_last_invoice(&self) -> Result<Invoice, LxpApiError> {
+        let r = self.get("getInvoice", &self.request()).await?;
+        decode_invoice(&r, &self.currency().await?)
+    }
+
+    pub async fn get_invoice(&self, id: i32) -> Result<Invoice, LxpApiError> {
+        let sub_url = format!("getInvoice/{}", id);
+        let r = self.get(&sub_url, &self.request()).await?;
+        decode_invoice(&r, &self.currency().await?)
+    }
+
+    /// Upload a PDF file as letter, the file name is used as address. Without
+    /// a dispatch date the letter is sent as soon as possible.
+    pub async fn set_job(
+        &self,
+        file_name: &str,
+        color: &ColorPrint,
+        mode: &Mode,
+        ship: &Ship,
+        dispatch_date: Option<NaiveDate>,
+    ) -> Result<JobCreated, LxpApiError> {
+        let path = std::path::Path::new(&file_name);
+        let pdf_content = std::fs::read(path).map_err(|source| LxpApiError::PdfFile {
+            path: path.into(),
+            source,
+        })?;
+        let address = path.file_name().unwrap().to_string_lossy();
+        self.set_job_from_bytes(&address, pdf_content, color, mode, ship, dispatch_date)
+            .await
+    }
+
+    /// Estimate the cost of a PDF file as letter without uploading it
+    pub fn estimate_job(
+        &self,
+        file_name: &str,
+        color: &ColorPrint,
+        mode: &Mode,
+        ship: &Ship,
+    ) -> Result<Estimate, LxpApiError> {
+        let path = std::path::Path::new(&file_name);
+        let pdf_content = std::fs::read(path).map_err(|source| LxpApiError::PdfFile {
+            path: path.into(),
+            source,
+        })?;
+        let address = path.file_name().unwrap().to_string_lossy();
+        self.estimate_job_from_bytes(&address, &pdf_content, color, mode, ship)
+    }
+
+    /// Estimate the cost of a PDF document held in memory
+    pub fn estimate_job_from_bytes(
+        &self,
+        address: &str,
+        pdf_content: &[u8],
+        color: &ColorPrint,
+        mode: &Mode,
+        ship: &Ship,
+    ) -> Result<Estimate, LxpApiError> {
+        let info = validate_pdf(pdf_content).map_err(|reason| LxpApiError::InvalidPdf {
+            name: address.into(),
+            reason,
+        })?;
+        Ok(self.tariff.estimate(info.pages, color, mode, ship))
+    }
+
+    /// Upload a PDF document read from `reader` as letter
+    pub async fn set_job_from_reader<R: AsyncRead + Unpin>(
+        &self,
+        address: &str,
+        mut reader: R,
+        color: &ColorPrint,
+        mode: &Mode,
+        ship: &Ship,
+        dispatch_date: Option<NaiveDate>,
+    ) -> Result<JobCreated, LxpApiError> {
         let mut pdf_content = Vec::new();
-        match pdf_file.read_to_end(&mut pdf_content) {
-            Err(why) => {
-                error!("couldn't read {}", why);
-                return Err(LxpApiError::PdfFileError);
-            },
-            Ok(_c) => (),
-        };
+        reader
+            .read_to_end(&mut pdf_content)
+            .await
+            .map_err(|source| LxpApiError::PdfFile {
+                path: address.into(),
+                source,
+            })?;
+        self.set_job_from_bytes(address, pdf_content, color, mode, ship, dispatch_date)
+            .await
+    }
+
+    /// Upload a PDF document held in memory as letter, `address` is the name
+    /// shown in the job lists. The document is checked locally before.
+    pub async fn set_job_from_bytes(
+        &self,
+        address: &str,
+        pdf_content: Vec<u8>,
+        color: &ColorPrint,
+        mode: &Mode,
+        ship: &Ship,
+        dispatch_date: Option<NaiveDate>,
+    ) -> Result<JobCreated, LxpApiError> {
+        let info = validate_pdf(&pdf_content).map_err(|reason| LxpApiError::InvalidPdf {
+            name: address.into(),
+            reason,
+        })?;
+        trace!("PDF {} version {}, {} page(s)", address, info.version, info.pages);
+        if let Some(date) = dispatch_date {
+            validate_dispatch_date(date)?;
+        }
+        let recipient = self.check_address(address, &pdf_content)?;
+        self.post_letter(address, pdf_content, info.pages, color, mode, ship, dispatch_date, recipient)
+            .await
+    }
+
+    // Upload a letter that passed the local checks
+    #[allow(clippy::too_many_arguments)]
+    async fn post_letter(
+        &self,
+        address: &str,
+        pdf_content: Vec<u8>,
+        pages: u32,
+        color: &ColorPrint,
+        mode: &Mode,
+        ship: &Ship,
+        dispatch_date: Option<NaiveDate>,
+        recipient: Vec<String>,
+    ) -> Result<JobCreated, LxpApiError> {
+        let mut letter = SubLetterData::default();
+        letter.specification.color = color.code();
+        letter.specification.mode = mode.as_str().into();
+        letter.specification.ship = ship.as_str().into();
+        letter.address = address.into();
+        letter.dispatchdate = dispatch_date;
 
         letter.base64_file = base64::encode(pdf_content);
         letter.base64_checksum = format!("{:x}", md5::compute(&letter.base64_file));
 
         let body = RequestLetter {
             auth: self.auth.clone(),
-            letter: letter,
+            letter,
         };
 
-        self.post("setJob", &body).await
+        // Sending a letter twice costs money, so a retry is only done when the
+        // first attempt certainly did not arrive, or when the server answered
+        // with an error and no job like the letter showed up on the server.
+        // Without an answer, e.g. after a timeout, the job may show up later.
+        let mut attempt = 1;
+        loop {
+            let e = match self.send(Method::POST, "setJob", &body).await {
+                Ok(r) => {
+                    return decode_job_created(&r).map(|job| JobCreated { recipient, ..job })
+                }
+                Err(e) if self.retry.retry(attempt, &e) => e,
+                Err(e) => return Err(e),
+            };
+            if !self.retry.is_unprocessed(&e) {
+                let job_ids = match self.find_pending_jobs(&body.letter, pages).await {
+                    Ok(job_ids) => job_ids,
+                    Err(lookup_error) => {
+                        debug!("Job lookup failed: {}", lookup_error);
+                        return Err(e);
+                    }
+                };
+                let unanswered = matches!(e, LxpApiError::Transport(_));
+                if !job_ids.is_empty() || unanswered {
+                    return Err(LxpApiError::Unconfirmed {
+                        job_ids,
+                        source: Box::new(e),
+                    });
+                }
+            }
+            self.retry_delay(attempt, &e).await;
+            attempt += 1;
+        }
     }
 
-    async fn delete(&self, sub_url: &str, body: &RequestLetter) -> Result<Response, LxpApiError> {
-        let url = self.url.clone() + sub_url;
-        trace!("Url {}", &url);
-        trace!("body {}", serde_json::to_string(body).unwrap());
+    // Ids of jobs on hold or in the queue with the address (file name), page
+    // count and print options of the letter
+    async fn find_pending_jobs(&self, letter: &SubLetterData, pages: u32) -> Result<Vec<i32>, LxpApiError> {
+        let mut jobs = self.get_jobs_hold().await?;
+        jobs.extend(self.get_jobs_queue(7).await?);
+        let spec = &letter.specification;
+        Ok(jobs
+            .iter()
+            .filter(|job| job.address == letter.address && job.pages == pages)
+            .filter(|job| job.color.code() == spec.color && job.mode.as_str() == spec.mode)
+            .filter(|job| job.shipping.as_str() == spec.ship)
+            .map(|job| job.id)
+            .collect())
+    }
 
-        let response = self.client.delete(&url).json(body).send().await;
-        self.handle_response(response).await
+    async fn retry_delay(&self, attempt: u32, e: &LxpApiError) {
+        let delay = self.retry.delay(attempt);
+        debug!("Attempt {} failed ({}), retry in {:?}", attempt, e, delay);
+        tokio::time::delay_for(delay).await;
+    }
+
+    async fn delete(&self, sub_url: &str, body: &RequestLetter) -> Result<Response, LxpApiError> {
+        // A delete is only repeated if the first attempt did not arrive,
+        // otherwise a retry would report a misleading 404
+        let mut attempt = 1;
+        loop {
+            match self.send(Method::DELETE, sub_url, body).await {
+                Err(e) if self.retry.retry(attempt, &e) && self.retry.is_unprocessed(&e) => {
+                    self.retry_delay(attempt, &e).await
+                }
+                result => return result,
+            }
+            attempt += 1;
+        }
     }
 
     async fn get(&self, sub_url: &str, body: &RequestLetter) -> Result<Response, LxpApiError> {
-        let url = self.url.clone() + sub_url;
-        trace!("Url {}", &url);
-        trace!("body {}", serde_json::to_string(body).unwrap());
+        self.idempotent(Method::GET, sub_url, body).await
+    }
 
-        let response = self.client.get(&url).json(body).send().await;
-        self.handle_response(response).await
+    async fn put(&self, sub_url: &str, body: &RequestLetter) -> Result<Response, LxpApiError> {
+        self.idempotent(Method::PUT, sub_url, body).await
     }
 
-    async fn post(&self, sub_url: &str, body: &RequestLetter) -> Result<Response, LxpApiError> {
+    // Requests that may be repeated without harm
+    async fn idempotent(
+        &self,
+        method: Method,
+        sub_url: &str,
+        body: &RequestLetter,
+    ) -> Result<Response, LxpApiError> {
+        let mut attempt = 1;
+        loop {
+            match self.send(method.clone(), sub_url, body).await {
+                Err(e) if self.retry.retry(attempt, &e) => self.retry_delay(attempt, &e).await,
+                result => return result,
+            }
+            attempt += 1;
+        }
+    }
+
+    // A single request without any retry
+    async fn send(
+        &self,
+        method: Method,
+        sub_url: &str,
+        body: &RequestLetter,
+    ) -> Result<Response, LxpApiError> {
         let url = self.url.clone() + sub_url;
-        trace!("Url {}", &url);
-        trace!("body {}", serde_json::to_string(body).unwrap());
+        trace!("{} Url {}", &method, &url);
+        if log_enabled!(Level::Trace) {
+            trace!("body {}", for_log(body));
+        }
 
-        let response = self.client.post(&url).json(body).send().await;
+        let _permit = self.throttle.acquire().await;
+        let response = self.client.request(method, &url).json(body).send().await;
         self.handle_response(response).await
     }
 
@@ -222,33 +635,79 @@ impl LxpApi {
         &self, 
         response: Result<reqwest::Response, reqwest::Error>
     ) -> Result<Response, LxpApiError> {
-        let r2 = match response {
-            Ok(r) => {
-                debug!("Response received");
-                r
-            }
-            Err(e) => {
-                debug!("{}", e);
-                return Err(LxpApiError::RestError);
-            }
-        };
+        let response = response.map_err(|e| {
+            debug!("{}", e);
+            LxpApiError::Transport(e)
+        })?;
+        debug!("Response received, HTTP status {}", response.status());
+        let http_status = response.status();
+
+        let json_res = response.text().await.map_err(|e| {
+            debug!("{}", e);
+            LxpApiError::Transport(e)
+        })?;
+        if log_enabled!(Level::Trace) {
+            trace!("Respond {}", text_for_log(&json_res));
+        }
 
-        let json_res = match r2.text().await {
+        let r = match serde_json::from_str::<Response>(&json_res) {
             Ok(r) => r,
             Err(e) => {
-                debug!("{}", e);
-                return Err(LxpApiError::RestError);
+                debug!("Respond was {}", text_for_log(&json_res));
+                debug!("Problem during JSON parsing: {}", e);
+                if !http_status.is_success() {
+                    return Err(LxpApiError::http(http_status, &json_res));
+                }
+                return Err(LxpApiError::json(e, &json_res));
             }
         };
-        trace!("Respond {}", &json_res);
 
-        match serde_json::from_str::<Response>(&json_res) {
-            Ok(r) => return Ok(r),
-            Err(e) => {
-                debug!("Respond was {}", &json_res);
-                debug!("Problem during JSON parsing: {}", e);
-                return Err(LxpApiError::JsonError);
+        // The web service reports its own status in the body, it takes
+        // precedence over the HTTP status
+        match r.status {
+            200 => Ok(r),
+            status => {
+                debug!("Web service status {}, message '{}'", status, &r.message);
+                Err(LxpApiError::from_status(status, &r.message))
             }
-        };
+        }
+    }
+}
+
+fn decode<T>(result: Result<T, String>) -> Result<T, LxpApiError> {
+    result.map_err(|message| LxpApiError::invalid(&message))
+}
+
+// Jobs of a job list, oldest first
+fn decode_jobs(r: &Response, currency: &str) -> Result<Vec<Job>, LxpApiError> {
+    let mut jobs = Vec::new();
+    if let Some(sub_jobs) = &r.jobs {
+        for job in sub_jobs.values() {
+            jobs.push(decode(Job::try_from((job, currency)))?);
+        }
+    }
+    jobs.sort_by_key(|job| (job.date, job.id));
+    Ok(jobs)
+}
+
+fn decode_invoice(r: &Response, currency: &str) -> Result<Invoice, LxpApiError> {
+    match &r.invoice {
+        Some(invoice) => decode(Invoice::try_from((invoice, currency))),
+        None => Err(LxpApiError::invalid("no invoice in answer")),
+    }
+}
+
+fn decode_job_created(r: &Response) -> Result<JobCreated, LxpApiError> {
+    match &r.letter {
+        Some(letter) => Ok(JobCreated {
+            id: decode(
+                letter
+                    .job_id
+                    .parse::<i32>()
+                    .map_err(|_| format!("job id '{}' is not a number", letter.job_id)),
+            )?,
+            recipient: Vec::new(),
+        }),
+        None => Err(LxpApiError::invalid("no job id in answer")),
     }
 }
@@ -1,53 +1,440 @@
-use crate::lxpapi;
 use crate::lxpconfig;
-use crate::lxptypes;
-use log::{info, debug, trace, error};
+use crate::lxpledger::{self, Ledger, LedgerEntry};
+use lxpapi::{lxptypes, Connection, Limits, LxpApi, LxpApiError, Money, RetryPolicy, SANDBOX_URL};
+use lxpapi::{merge_pdfs, parse_page_ranges, split_pdf, validate_pdf, AddressCheck, MergeError, PdfPart, SplitRule};
+use chrono::{Local, NaiveDate};
+use log::{info, debug, trace, warn, error};
 use std::fs;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use futures::{stream, StreamExt};
 use notify::{Watcher, RecursiveMode, watcher};
 use std::sync::mpsc::channel;
-use std::time::Duration;
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Limits given on the command line, they take precedence over the profile
+#[derive(Debug, Default, Clone)]
+pub struct LimitArgs {
+    pub concurrency: Option<usize>,
+    pub requests_per_second: Option<f64>,
+    pub connect_timeout: Option<u64>,
+    pub request_timeout: Option<u64>,
+}
+
+/// How PDF files are split into letters
+#[derive(Debug, Clone, PartialEq)]
+pub enum SplitArg {
+    /// Letters with a fixed number of pages
+    Pages(u32),
+    /// Page ranges read from the file <name>.ranges next to the PDF file
+    Ranges,
+    /// Pages containing the marker text separate the letters
+    Text(String),
+    /// Blank pages separate the letters
+    BlankPage,
+}
+
+/// Options of uploaded letters, given on the command line
+#[derive(Debug, Clone)]
+pub struct SetArgs {
+    pub color: lxptypes::ColorPrint,
+    pub mode: lxptypes::Mode,
+    pub ship: lxptypes::Ship,
+    pub dispatch_date: Option<NaiveDate>,
+    pub split: Option<SplitArg>,
+    /// Replaces the address check of the profile
+    pub address_check: Option<AddressCheck>,
+    /// Send letters even if the ledger or the server know them
+    pub force: bool,
+}
+
+/// Polling of job --track and set --track
+#[derive(Debug, Clone)]
+pub struct TrackArgs {
+    pub interval: Duration,
+    /// Give up after this time, wait for ever if not given
+    pub timeout: Option<Duration>,
+}
+
+/// Exit codes of the tracking, 1 is taken by errors
+pub const TRACK_SENT: i32 = 0;
+pub const TRACK_DELETED: i32 = 2;
+pub const TRACK_HOLD: i32 = 3;
+pub const TRACK_QUEUE: i32 = 4;
 
 #[derive(Debug, Clone)]
 pub struct LxpCommands {
     config: lxpconfig::LxpConfig,
-    api_ref: Option<lxpapi::LxpApi>,
+    api_ref: Option<LxpApi>,
+    limit_args: LimitArgs,
+    // --sandbox given, the test environment is used with any profile
+    sandbox: bool,
+    // Job ids of the letters uploaded by this run
+    uploaded: Arc<Mutex<Vec<i32>>>,
 }
 
 impl LxpCommands {
-    pub fn new(config_dir: &PathBuf) -> LxpCommands {
+    pub fn new(config_dir: &PathBuf, limit_args: LimitArgs, sandbox: bool) -> LxpCommands {
         let config = lxpconfig::LxpConfig::new(config_dir);
-        LxpCommands { config, api_ref: None }
+        LxpCommands { config, api_ref: None, limit_args, sandbox, uploaded: Default::default() }
+    }
+
+    /// Does the run use the test environment, by --sandbox or the profile?
+    pub fn is_sandbox(&self) -> bool {
+        self.sandbox
+            || self
+                .config
+                .get_active_profile_name()
+                .and_then(|name| self.config.get_profile(&name))
+                .and_then(|profile| profile.sandbox)
+                .unwrap_or(false)
     }
 
-    fn api(&mut self) -> lxpapi::LxpApi {
+    fn api(&mut self) -> LxpApi {
         match &self.api_ref {
             Some(_api) => (),
             None => {
                 // Get profile and instanciate api
                 let profile = self.config.get_active_profile().unwrap();
-                self.api_ref = Some(lxpapi::LxpApi::new(&profile.user_name, &profile.api_key, &profile.url))
+                self.api_ref = Some(self.build_api(profile));
             },
         };
         self.api_ref.clone().unwrap()
     }
 
-    pub fn profile_new(&mut self, profile_name: &str, user_name: &str, url: &str, api_key: &str) {
+    fn build_api(&self, profile: lxpconfig::Profile) -> LxpApi {
+        let mut retry = RetryPolicy::default();
+        if let Some(attempts) = profile.retry_attempts {
+            retry.max_attempts = attempts.max(1);
+        }
+        let args = &self.limit_args;
+        let mut limits = Limits::default();
+        if let Some(concurrency) = args.concurrency.or(profile.concurrency) {
+            limits.max_concurrent = concurrency.max(1);
+        }
+        limits.requests_per_second =
+            args.requests_per_second.or(profile.requests_per_second);
+        if let Some(secs) = args.connect_timeout.or(profile.connect_timeout) {
+            limits.connect_timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = args.request_timeout.or(profile.request_timeout) {
+            limits.request_timeout = Duration::from_secs(secs);
+        }
+        let connection = Connection {
+            proxy: profile.proxy,
+            proxy_user: profile.proxy_user,
+            proxy_password: profile.proxy_password,
+            ca_certificates: profile.ca_certificate.into_iter().collect(),
+            accept_invalid_certs: profile.accept_invalid_certs.unwrap_or(false),
+        };
+        let mut api = LxpApi::new(&profile.user_name, &profile.api_key, &profile.url)
+            .with_retry_policy(retry)
+            .with_limits(limits)
+            .with_tariff(profile.tariff.unwrap_or_default())
+            .with_address_check(
+                profile.address_check.unwrap_or_default(),
+                profile.address_window.unwrap_or_default(),
+            );
+        if self.sandbox || profile.sandbox.unwrap_or(false) {
+            api = api.with_sandbox(profile.sandbox_url.as_deref().unwrap_or(SANDBOX_URL));
+        }
+        match api.with_connection(&connection) {
+            Ok(api) => api,
+            Err(e) => {
+                error!("{}", e); // exits app
+                unreachable!()
+            }
+        }
+    }
+
+    // The api for uploads, with the address check of the command line
+    fn upload_api(&mut self, args: &SetArgs) -> LxpApi {
+        let api = self.api();
+        match args.address_check {
+            Some(check) => {
+                let window = api.address_window().clone();
+                api.with_address_check(check, window)
+            }
+            None => api,
+        }
+    }
+
+    // Everything needed to upload letters: the api, the ledger of the letters
+    // sent before and the archive
+    fn uploader(&mut self, args: &SetArgs) -> (LxpApi, Ledger, PathBuf) {
+        let api = self.upload_api(args);
+        (api, self.ledger(), self.archive_dir())
+    }
+
+    // The ledger and job history of the profile
+    fn ledger(&mut self) -> Ledger {
+        let days = self
+            .config
+            .get_active_profile()
+            .and_then(|profile| profile.duplicate_window_days)
+            .unwrap_or(lxpledger::DEFAULT_WINDOW_DAYS);
+        Ledger::load(&self.archive_dir(), days).collect_uploads(self.uploaded.clone())
+    }
+
+    /// Take over the state of the jobs on the server into the job history,
+    /// sent jobs are listed for the given number of days
+    pub async fn sync(&mut self, days: i32) {
+        let api = self.api();
+        let ledger = self.ledger();
+        let mut jobs = Vec::new();
+        let lists = vec![
+            api.get_jobs_hold().await,
+            api.get_jobs_queue(days).await,
+            api.get_jobs_sent(days).await,
+        ];
+        for listed in lists {
+            match listed {
+                Ok(listed) => jobs.extend(listed),
+                Err(e) => error!("Could not get the jobs from the server: {}", e), // exits app
+            }
+        }
+        let archive_dir = self.archive_dir();
+        let mut count = lxpledger::SyncCount::default();
+        ledger.sync_jobs(&jobs, &mut count);
+        for job in jobs.iter().filter(|job| is_final(job.status.as_str())) {
+            drop_archived(&archive_dir, job.id);
+        }
+
+        // Jobs no longer listed were sent long ago or deleted
+        let listed: Vec<i32> = jobs.iter().map(|job| job.id).collect();
+        for id in ledger.open_jobs().into_iter().filter(|id| !listed.contains(id)) {
+            match api.get_job(id).await {
+                Ok(job) => {
+                    if is_final(job.status.as_str()) {
+                        drop_archived(&archive_dir, id);
+                    }
+                    ledger.sync_jobs(&[job], &mut count)
+                }
+                Err(LxpApiError::NotFound { .. }) => {
+                    match ledger.mark_missing(id) {
+                        true => count.deleted += 1,
+                        false => count.unknown += 1,
+                    }
+                    drop_archived(&archive_dir, id);
+                }
+                // keep going with the other jobs
+                Err(e) => warn!("Job {} not synchronized: {}", id, e),
+            }
+        }
+        if let Err(e) = ledger.save() {
+            error!("Could not write the job history: {}", e); // exits app
+        }
         info!(
-            "New profile {}, user '{}', url '{}' and <api_key>",
-            profile_name, user_name, url
+            "Job history synchronized: {} updated, {} added, {} deleted, {} unknown",
+            count.updated, count.added, count.deleted, count.unknown
         );
-        info!("Active profile is set to '{}'", profile_name);
+    }
+
+    /// Ids of the jobs in the history that are neither sent nor deleted
+    pub fn open_jobs(&mut self) -> Vec<i32> {
+        self.ledger().open_jobs()
+    }
+
+    /// Ids of the jobs uploaded by this run
+    pub fn session_jobs(&mut self) -> Vec<i32> {
+        self.uploaded.lock().unwrap().clone()
+    }
+
+    /// Poll the job lists until the jobs are sent or deleted, printing every
+    /// change of their state. Returns the exit code: TRACK_SENT if all jobs
+    /// were sent, TRACK_DELETED if one was deleted, TRACK_HOLD or TRACK_QUEUE
+    /// if the timeout passed with a job on hold (lack of credit) or in the
+    /// queue.
+    pub async fn job_track(&mut self, ids: &[i32], args: &TrackArgs) -> i32 {
+        if ids.is_empty() {
+            info!("No jobs to track");
+            return TRACK_SENT;
+        }
+        let api = self.api();
+        let ledger = self.ledger();
+        let archive_dir = self.archive_dir();
+        let start = Instant::now();
+        // address and status of the tracked jobs
+        let mut states: BTreeMap<i32, (String, String)> = BTreeMap::new();
+        info!("Tracking {} job(s), every {} seconds", ids.len(), args.interval.as_secs());
+        loop {
+            let mut jobs = Vec::new();
+            let lists = vec![
+                api.get_jobs_hold().await,
+                api.get_jobs_queue(7).await,
+                api.get_jobs_sent(7).await,
+            ];
+            for listed in lists {
+                match listed {
+                    Ok(listed) => jobs.extend(listed),
+                    // the next round may get them
+                    Err(e) => warn!("Could not get the jobs from the server: {}", e),
+                }
+            }
+            let mut count = lxpledger::SyncCount::default();
+            for id in ids {
+                if matches!(states.get(id), Some((_, status)) if is_final(status)) {
+                    continue;
+                }
+                let job = match jobs.iter().find(|job| job.id == *id) {
+                    Some(job) => Ok(job.clone()),
+                    None => api.get_job(*id).await,
+                };
+                let state = match job {
+                    Ok(job) => {
+                        ledger.sync_jobs(std::slice::from_ref(&job), &mut count);
+                        (job.address, job.status.as_str().to_string())
+                    }
+                    Err(LxpApiError::NotFound { .. }) => {
+                        let status = match ledger.mark_missing(*id) {
+                            true => "deleted",
+                            false => "unknown",
+                        };
+                        let address = states.get(id).map(|(address, _)| address.clone());
+                        (address.unwrap_or_default(), status.to_string())
+                    }
+                    Err(e) => {
+                        warn!("Job {} not tracked this time: {}", id, e);
+                        continue;
+                    }
+                };
+                if is_final(&state.1) {
+                    drop_archived(&archive_dir, *id);
+                }
+                match states.get(id) {
+                    None => info!("  Job {} {}: {}", id, state.0, state.1),
+                    Some((_, before)) if *before != state.1 => {
+                        info!("  Job {} {}: {} -> {}", id, state.0, before, state.1)
+                    }
+                    _ => (),
+                }
+                states.insert(*id, state);
+            }
+            if let Err(e) = ledger.save() {
+                warn!("Could not write the job history: {}", e);
+            }
+
+            let done = ids
+                .iter()
+                .all(|id| matches!(states.get(id), Some((_, status)) if is_final(status)));
+            if done {
+                break;
+            }
+            // the last round is run when the timeout is reached
+            let wait = match args.timeout {
+                None => args.interval,
+                Some(timeout) => match timeout.checked_sub(start.elapsed()) {
+                    Some(left) if left > Duration::from_secs(0) => left.min(args.interval),
+                    _ => break,
+                },
+            };
+            tokio::time::delay_for(wait).await;
+        }
+
+        let with_status = |wanted: &str| {
+            states
+                .iter()
+                .filter(|(_, (_, status))| status == wanted)
+                .map(|(id, _)| id.to_string())
+                .collect::<Vec<_>>()
+        };
+        let deleted = with_status("deleted");
+        let hold = with_status("hold");
+        let sent = with_status("sent");
+        if !deleted.is_empty() {
+            info!("Deleted job(s): {}", deleted.join(", "));
+            TRACK_DELETED
+        } else if !hold.is_empty() {
+            info!("Still on hold for lack of credit: {}", hold.join(", "));
+            TRACK_HOLD
+        } else if sent.len() < ids.len() {
+            info!("Not sent yet: {} of {} job(s)", ids.len() - sent.len(), ids.len());
+            TRACK_QUEUE
+        } else {
+            info!("All {} job(s) sent", ids.len());
+            TRACK_SENT
+        }
+    }
+
+    // Jobs of the test environment are kept apart, their ids are unrelated
+    fn archive_dir(&mut self) -> PathBuf {
+        match self.api().is_sandbox() {
+            true => self.config.archive_dir().join("sandbox"),
+            false => self.config.archive_dir(),
+        }
+    }
+
+    pub async fn profile_new(
+        &mut self,
+        profile_name: &str,
+        user_name: &str,
+        url: &str,
+        api_key: &str,
+        verify: bool,
+    ) {
         let profile = lxpconfig::Profile {
             user_name: user_name.into(),
             url: url.into(),
             api_key: api_key.into(),
+            sandbox: if self.sandbox { Some(true) } else { None },
+            ..Default::default()
         };
+        if verify {
+            // A profile that can't access the service is not stored
+            self.check_access(profile_name, &profile).await;
+        }
+        info!(
+            "New profile {}, user '{}', url '{}' and <api_key>",
+            profile_name, user_name, url
+        );
+        info!("Active profile is set to '{}'", profile_name);
         self.config.new_profile(profile_name, profile);
     }
 
+    /// Check access with the given or the active profile
+    pub async fn profile_test(&mut self, profile_name: Option<&str>) {
+        let profile_name = match profile_name {
+            Some(name) => name.to_string(),
+            None => match self.config.get_active_profile_name() {
+                Some(name) => name,
+                None => {
+                    error!("No active profile found"); // exits app
+                    return;
+                }
+            },
+        };
+        match self.config.get_profile(&profile_name) {
+            Some(profile) => self.check_access(&profile_name, &profile).await,
+            None => error!("Could not test profile '{}': not found", profile_name), // exits app
+        }
+    }
+
+    async fn check_access(&self, profile_name: &str, profile: &lxpconfig::Profile) {
+        info!("Testing profile '{}', url '{}'", profile_name, profile.url);
+        match self.build_api(profile.clone()).check_access().await {
+            Ok(check) => {
+                info!("{:<14} {}", "User", check.user.as_deref().unwrap_or(&profile.user_name));
+                if let Some(id) = &check.account_id {
+                    info!("{:<14} {}", "Account", id);
+                }
+                if let Some(status) = &check.account_status {
+                    info!("{:<14} {}", "Status", status);
+                }
+                info!("{:<14} {}", "Credit balance", check.balance);
+                info!("{:<14} {} ms", "Latency", check.latency.as_millis());
+                info!("Access to the web service is working");
+            }
+            Err(e) => error!(
+                "Access to the web service failed: {}\n{}",
+                e,
+                diagnosis(&e, &profile.url)
+            ), // exits app
+        }
+    }
+
     pub fn profile_delete(&mut self, profile_name: &str) {
         self.config.delete_profile(profile_name);
     }
@@ -64,45 +451,48 @@ impl LxpCommands {
         self.config.show_profiles();
     }
 
-    fn _invoice_write_pdf_file(&self, r: lxptypes::Response, pdf_file: Vec<u8>) {
+    fn _invoice_write_pdf_file(&self, invoice: lxptypes::Invoice) {
         let profile_name = self.config.get_active_profile_name().unwrap();
-        match &r.invoice {
-            Some(invoice) => {
-                let file_name: String =
-                    format!("{}_{}-invoice.pdf", invoice.invoicedate, &profile_name);
-                info!("Writing file '{}'", file_name);
-                let mut buffer = fs::File::create(file_name).expect("Could not create PDF file");
-                buffer
-                    .write_all(&pdf_file)
-                    .expect("Could not write PDF file");
-            }
-            None => error!("<No data>"),
-        }
+        let file_name: String = format!(
+            "{}_{}-invoice.pdf",
+            invoice.meta.date.format("%Y-%m-%d"),
+            &profile_name
+        );
+        info!("Writing file '{}'", file_name);
+        let mut buffer = fs::File::create(file_name).expect("Could not create PDF file");
+        buffer
+            .write_all(&invoice.pdf)
+            .expect("Could not write PDF file");
     }
 
     pub async fn invoice_list(&mut self) {
         match self.api().list_invoices().await {
-            Ok(r) => match &r.invoices {
-                Some(invoices) => {
-                    info!("\n{:<10} {:>6} {:>8}", "Date", "Id", "Cost");
-                    for (_key, invoice) in invoices {
-                        let cost = invoice.sum.parse::<f64>().unwrap()
-                            + invoice.vat.parse::<f64>().unwrap();
-                        info!(
-                            "{:<10} {:>6} {:>6.2} €",
-                            &invoice.invoicedate, &invoice.iid, &cost,
-                        )
-                    }
+            Ok(invoices) if invoices.is_empty() => info!("<No data>"),
+            Ok(invoices) => {
+                info!("\n{:<10} {:>6} {:>8}", "Date", "Id", "Cost");
+                for invoice in &invoices {
+                    let cost = match invoice.sum.checked_add(&invoice.vat) {
+                        Ok(cost) => cost,
+                        Err(e) => {
+                            error!("Invoice {} not listed: {}", invoice.id, e); // exits app
+                            unreachable!()
+                        }
+                    };
+                    info!(
+                        "{:<10} {:>6} {:>8}",
+                        invoice.date.format("%Y-%m-%d").to_string(),
+                        &invoice.id,
+                        &cost
+                    )
                 }
-                None => info!("<No data>"),
-            },
+            }
             Err(e) => error!("Error when getting invoice list {}", e),
         }
     }
 
     pub async fn invoice_get_last(&mut self) {
         match self.api().get_last_invoice().await {
-            Ok(r) => self._invoice_write_pdf_file(r.0, r.1),
+            Ok(invoice) => self._invoice_write_pdf_file(invoice),
             Err(e) => error!("Error when getting invoice {}", e),
         }
     }
@@ -112,7 +502,7 @@ impl LxpCommands {
             Ok(id) => {
                 debug!("Storing invoice, ID: {}", id);
                 match self.api().get_invoice(id).await {
-                    Ok(r) => self._invoice_write_pdf_file(r.0, r.1),
+                    Ok(invoice) => self._invoice_write_pdf_file(invoice),
                     Err(e) => error!("Error when getting invoice {}", e),
                 }
             }
@@ -122,52 +512,66 @@ impl LxpCommands {
         }
     }
 
-    fn _job_show_list(&self, r: lxptypes::Response) {
-        match &r.jobs {
-            Some(jobs) => {
-                let mut sum_cost: f64 = 0.0;
-                info!(
-                    "\n{:<10} {:>8} {:>3} {:>3} {:>3} {:>3} {:>4} {:<35}",
-                    "Date", "Id", "Pgs", "Col", "Dpx", "Shp", "Cost", "Filename"
-                );
-                for (_key, job) in jobs {
-                    let cost =
-                        job.cost.parse::<f64>().unwrap() + job.cost_vat.parse::<f64>().unwrap();
-                    sum_cost += cost;
-                    info!(
-                        "{:<10} {:>8} {:>3} {:>3} {:>3} {:>3} {:>4.2} {:<35}",
-                        &job.date[..10],
-                        &job.jid,
-                        &job.pages,
-                        &job.color,
-                        &job.mode[..3],
-                        &job.shipping[..3],
-                        &cost,
-                        &job.address
-                    )
+    fn _job_show_list(&self, jobs: &[lxptypes::Job]) {
+        if jobs.is_empty() {
+            info!("<No data>");
+            return;
+        }
+        let mut sum_cost = lxptypes::Money::zero(jobs[0].cost.currency());
+        info!(
+            "\n{:<10} {:<10} {:>8} {:>3} {:>3} {:>3} {:>3} {:>6} {:<35}",
+            "Date", "Dispatch", "Id", "Pgs", "Col", "Dpx", "Shp", "Cost", "Filename"
+        );
+        for job in jobs {
+            let cost = match job
+                .cost
+                .checked_add(&job.cost_vat)
+                .and_then(|cost| sum_cost.checked_add(&cost).map(|sum| (cost, sum)))
+            {
+                Ok((cost, sum)) => {
+                    sum_cost = sum;
+                    cost
                 }
-                info!("The sum of the costs is {:.2} €", sum_cost)
-            }
-            None => info!("<No data>"),
+                Err(e) => {
+                    error!("Costs of job {} not summed up: {}", job.id, e); // exits app
+                    unreachable!()
+                }
+            };
+            info!(
+                "{:<10} {:<10} {:>8} {:>3} {:>3} {:>3} {:>3} {:>6} {:<35}",
+                job.date.with_timezone(&Local).format("%Y-%m-%d").to_string(),
+                match job.dispatchdate {
+                    Some(date) => date.format("%Y-%m-%d").to_string(),
+                    None => String::new(),
+                },
+                &job.id,
+                &job.pages,
+                &job.color.code(),
+                &job.mode.as_str()[..3],
+                &job.shipping.as_str()[..3],
+                &cost,
+                &job.address
+            )
         }
+        info!("The sum of the costs is {}", sum_cost)
     }
 
-    async fn _job_show_lists(&mut self) -> Result<(), lxpapi::LxpApiError> {
-        let r = self.api().get_blance().await?;
-        info!("Credit balance {} €", r.balance.unwrap().value);
+    async fn _job_show_lists(&mut self) -> Result<(), LxpApiError> {
+        let balance = self.api().get_balance().await?;
+        info!("Credit balance {}", balance.value);
 
         debug!("Check the status of the placed print jobs");
-        let r = self.api().get_jobs_queue(7).await?;
+        let jobs = self.api().get_jobs_queue(7).await?;
         info!("\nThese letters will be sent soon:");
-        self._job_show_list(r);
+        self._job_show_list(&jobs);
 
-        let r = self.api().get_jobs_hold().await?;
+        let jobs = self.api().get_jobs_hold().await?;
         info!("\nThese letters are in the queue (credit exhausted):");
-        self._job_show_list(r);
+        self._job_show_list(&jobs);
 
-        let r = self.api().get_jobs_sent(7).await?;
+        let jobs = self.api().get_jobs_sent(7).await?;
         info!("\nThese letters are sent in the last 7 days:");
-        self._job_show_list(r);
+        self._job_show_list(&jobs);
         Ok(())
     }
 
@@ -188,36 +592,31 @@ impl LxpCommands {
 
     async fn _job_delete_by_id(&mut self, id: i32, file_name: &str) {
         match self.api().delete_job(id).await {
-            Ok(r) => match r.status {
-                200 => info!("  Job id {} {} deleted", id, file_name),
-                404 => error!("Job Id {} not found", id),
-                _ => error!("Don't know what to do with status {}", r.status),
-            },
-            Err(e) => error!("Error in server connection {}", e),
+            Ok(()) => {
+                info!("  Job id {} {} deleted", id, file_name);
+                self.ledger().mark_deleted(id);
+                drop_archived(&self.archive_dir(), id);
+            }
+            Err(LxpApiError::NotFound { .. }) => error!("Job Id {} not found", id),
+            Err(LxpApiError::Conflict { .. }) => {
+                error!("Job Id {} can no longer be deleted", id)
+            }
+            Err(e) => error!("Error when deleting job {}: {}", id, e),
         }
     }
 
-    async fn _jobs_delete_list(&mut self, r: lxptypes::Response) -> i32 {
+    async fn _jobs_delete_list(&mut self, jobs: Vec<lxptypes::Job>) -> i32 {
         let mut jobs_deleted: i32 = 0;
-        match &r.jobs {
-            Some(jobs) => {
-                for (_key, job) in jobs {
-                    let id = job
-                        .jid
-                        .parse::<i32>()
-                        .expect("Job id must be integer, error in JSON string");
-                    self._job_delete_by_id(id, &job.address).await;
-                    jobs_deleted += 1;
-                }
-            }
-            None => (),
+        for job in &jobs {
+            self._job_delete_by_id(job.id, &job.address).await;
+            jobs_deleted += 1;
         }
         jobs_deleted
     }
 
     pub async fn job_delete_all(&mut self) {
         let mut jobs_deleted: i32 = match self.api().get_jobs_queue(7).await {
-            Ok(r) => self._jobs_delete_list(r).await,
+            Ok(jobs) => self._jobs_delete_list(jobs).await,
             Err(e) => {
                 error!("{}", e);
                 0
@@ -225,7 +624,7 @@ impl LxpCommands {
         };
 
         jobs_deleted += match self.api().get_jobs_hold().await {
-            Ok(r) => self._jobs_delete_list(r).await,
+            Ok(jobs) => self._jobs_delete_list(jobs).await,
             Err(e) => {
                 error!("{}", e);
                 0
@@ -248,40 +647,157 @@ impl LxpCommands {
         self._job_delete_by_id(id, "").await;
     }
 
-    pub async fn job_set_file_or_dir(
+    pub async fn job_show(&mut self, id_arg: &str) {
+        let id = match id_arg.parse::<i32>() {
+            Ok(id) => id,
+            Err(e) => {
+                error!("Job id must be Integer: Error Message '{}'", e);
+                0
+            }
+        };
+        let job = match self.api().get_job(id).await {
+            Ok(job) => job,
+            Err(LxpApiError::NotFound { .. }) => {
+                error!("Job Id {} not found", id);
+                return;
+            }
+            Err(e) => {
+                error!("Error when getting job {}: {}", id, e);
+                return;
+            }
+        };
+        let optional = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+        info!("{:<14} {}", "Id", job.id);
+        info!("{:<14} {}", "Address", job.address);
+        info!("{:<14} {}", "Status", job.status.as_str());
+        info!("{:<14} {}", "Parent", optional(job.parent.map(|id| id.to_string())));
+        info!("{:<14} {}", "Pages", job.pages);
+        info!("{:<14} {}", "Color", job.color.code());
+        info!("{:<14} {}", "Mode", job.mode.as_str());
+        info!("{:<14} {}", "Shipping", job.shipping.as_str());
+        info!("{:<14} {}", "Cover", job.cover);
+        info!("{:<14} {}", "Cost", job.cost);
+        info!("{:<14} {}", "VAT", job.cost_vat);
+        info!(
+            "{:<14} {}",
+            "Date",
+            job.date.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")
+        );
+        info!(
+            "{:<14} {}",
+            "Dispatch date",
+            optional(job.dispatchdate.map(|date| date.format("%Y-%m-%d").to_string()))
+        );
+        info!(
+            "{:<14} {}",
+            "Sent date",
+            optional(job.sentdate.map(|date| {
+                date.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string()
+            }))
+        );
+    }
+
+    /// Change the print options of a job, options not given are kept
+    pub async fn job_update(
         &mut self,
-        file_or_dir_name: &str,
-        color: lxptypes::ColorPrint,
-        mode: lxptypes::Mode,
-        ship: lxptypes::Ship,
+        id_arg: &str,
+        color: Option<lxptypes::ColorPrint>,
+        mode: Option<lxptypes::Mode>,
+        ship: Option<lxptypes::Ship>,
     ) {
+        let id = match id_arg.parse::<i32>() {
+            Ok(id) => id,
+            Err(e) => {
+                error!("Job id must be Integer: Error Message '{}'", e);
+                0
+            }
+        };
+        let job = match self.api().get_job(id).await {
+            Ok(job) => job,
+            Err(LxpApiError::NotFound { .. }) => {
+                error!("Job Id {} not found", id);
+                return;
+            }
+            Err(e) => {
+                error!("Error when getting job {}: {}", id, e);
+                return;
+            }
+        };
+        let color = color.unwrap_or(job.color);
+        let mode = mode.unwrap_or(job.mode);
+        let ship = ship.unwrap_or(job.shipping);
+
+        let archive_dir = self.archive_dir();
+        let archived_path = archive_dir.join(format!("{}.pdf", id));
+        let archived_copy = fs::read(&archived_path).ok();
+        match self.api().update_job(id, &color, &mode, &ship, archived_copy).await {
+            Ok(updated) if updated.resubmitted => {
+                info!("  Job {} {} replaced by job {}", id, job.address, updated.id);
+                self.ledger().update_job(id, updated.id, &color, &mode, &ship);
+                let new_path = archive_dir.join(format!("{}.pdf", updated.id));
+                if let Err(e) = fs::rename(&archived_path, &new_path) {
+                    warn!("Could not archive job {}: {}", updated.id, e);
+                }
+            }
+            Ok(_) => {
+                info!("  Job {} {} updated", id, job.address);
+                self.ledger().update_job(id, id, &color, &mode, &ship);
+            }
+            Err(LxpApiError::Conflict { message }) => {
+                error!("Job Id {} can't be changed: {}", id, message)
+            }
+            Err(e) => error!("Job Id {} not changed: {}", id, e),
+        }
+    }
+
+    pub async fn job_set_file_or_dir(&mut self, file_or_dir_name: &str, args: &SetArgs) {
         match std::fs::metadata(file_or_dir_name) {
             Ok(md) => {
                 if md.is_file() {
-                    match self.api().set_job(&file_or_dir_name, &color, &mode, &ship).await {
-                        Ok(_r) => info!("  Job {} sent", &file_or_dir_name),
-                        Err(_) => (), // Error message was already issued by set_job()
+                    if let Some(split) = &args.split {
+                        let path = Path::new(file_or_dir_name);
+                        if let Err(e) = self.set_split_file(path, split, args).await {
+                            error!("Job {} not sent: {}", &file_or_dir_name, e);
+                        }
+                        return;
+                    }
+                    let path = Path::new(file_or_dir_name);
+                    let (api, ledger, archive_dir) = self.uploader(args);
+                    match upload_file(&api, &ledger, &archive_dir, path, args).await {
+                        Ok(r) => info!("  Job {} sent{}", &file_or_dir_name, sent_to(&r)),
+                        Err(e) => error!("Job {} not sent: {}", &file_or_dir_name, e),
                     }
                 };
                 if md.is_dir() {
+                    if let Some(split) = &args.split {
+                        // One file after the other, the letters of a file in parallel
+                        for path in pdf_files(file_or_dir_name) {
+                            if let Err(e) = self.set_split_file(&path, split, args).await {
+                                warn!("Job {} not sent: {}", path.display(), e);
+                            }
+                        }
+                        return;
+                    }
                     if let Ok(entries) = std::fs::read_dir(file_or_dir_name) {
-                        let api = &self.api();
+                        let (api, ledger, archive_dir) = &self.uploader(args);
+                        let concurrency = api.limits().max_concurrent;
                         let puts = stream::iter(
                             entries.into_iter().map(|entry| {
                                 async move {
                                     if let Ok(entry) = entry {
                                         let path = entry.path();
-                                        if path.is_file() {
+                                        if path.is_file() && has_pdf_extension(&path) {
                                             let p = path.to_str().unwrap();
-                                            match api.set_job(&p, &color, &mode, &ship).await {
-                                                Ok(_r) => info!("  Job {} sent", &p),
-                                                Err(_) => (), // Error message was already issued by set_job()
+                                            match upload_file(api, ledger, archive_dir, &path, args).await {
+                                                Ok(r) => info!("  Job {} sent{}", &p, sent_to(&r)),
+                                                // keep going with the other files
+                                                Err(e) => warn!("Job {} not sent: {}", &p, e),
                                             }
                                         }
                                     }                                
                                 }
                             })
-                        ).buffer_unordered(5).collect::<Vec<()>>();  // up to 5 concurrent async requests
+                        ).buffer_unordered(concurrency).collect::<Vec<()>>();
                         puts.await;
                     }
                 }
@@ -289,19 +805,131 @@ impl LxpCommands {
             Err(e) => error!("Opening send file: {}", e),
         };
     }
-    pub async fn watch_dir(
+
+    // Upload the letters of a PDF file that is split, fails if any of them
+    // was not sent
+    async fn set_split_file(
         &mut self,
-        dir_name: &PathBuf,
-        color: lxptypes::ColorPrint,
-        mode: lxptypes::Mode,
-        ship: lxptypes::Ship,
-    ) {
+        path: &Path,
+        split: &SplitArg,
+        args: &SetArgs,
+    ) -> Result<(), String> {
+        let letters = split_file(path, split)?;
+        info!("  File {} split into {} letters", path.display(), letters.len());
+        let (api, ledger, archive_dir) = &self.uploader(args);
+        let count = letters.len();
+        let puts = stream::iter(letters.into_iter().map(|(address, part)| async move {
+            let files = [path.into()];
+            match upload_letter(api, ledger, archive_dir, &address, part.pdf, &files, Some(&part.pages), args).await {
+                Ok(r) => {
+                    info!("  Job {} sent{}", &address, sent_to(&r));
+                    true
+                }
+                // keep going with the other letters
+                Err(e) => {
+                    warn!("Job {} not sent: {}", &address, e);
+                    false
+                }
+            }
+        }))
+        .buffer_unordered(api.limits().max_concurrent)
+        .collect::<Vec<bool>>();
+        match puts.await.iter().filter(|sent| !**sent).count() {
+            0 => Ok(()),
+            failed => Err(format!("{} of {} letters not sent", failed, count)),
+        }
+    }
+
+    pub async fn job_estimate_file_or_dir(&mut self, file_or_dir_name: &str, args: &SetArgs) {
+        let api = self.api();
+        let (color, mode, ship) = (&args.color, &args.mode, &args.ship);
+        // The letters of a file, the file itself if it is not split
+        let letters_of = |path: &Path| -> Result<Vec<(String, Vec<u8>)>, String> {
+            match &args.split {
+                Some(split) => split_file(path, split)
+                    .map(|letters| letters.into_iter().map(|(name, part)| (name, part.pdf)).collect()),
+                None => match fs::read(path) {
+                    Ok(pdf) => Ok(vec![(path.file_name().unwrap_or_default().to_string_lossy().into(), pdf)]),
+                    Err(e) => Err(e.to_string()),
+                },
+            }
+        };
+        let mut estimates = Vec::new();
+        match std::fs::metadata(file_or_dir_name) {
+            Ok(md) if md.is_dir() => {
+                for path in pdf_files(file_or_dir_name) {
+                    match letters_of(&path) {
+                        Ok(letters) => {
+                            for (name, pdf) in letters {
+                                match api.estimate_job_from_bytes(&name, &pdf, color, mode, ship) {
+                                    Ok(estimate) => estimates.push((name, estimate)),
+                                    // keep going with the other files
+                                    Err(e) => warn!("Job {} not estimated: {}", &name, e),
+                                }
+                            }
+                        }
+                        Err(e) => warn!("Job {} not estimated: {}", path.display(), e),
+                    }
+                }
+            }
+            Ok(_md) => match letters_of(Path::new(file_or_dir_name)) {
+                Ok(letters) => {
+                    for (name, pdf) in letters {
+                        match api.estimate_job_from_bytes(&name, &pdf, color, mode, ship) {
+                            Ok(estimate) => estimates.push((name, estimate)),
+                            Err(e) => error!("Job {} not estimated: {}", &name, e),
+                        }
+                    }
+                }
+                Err(e) => error!("Job {} not estimated: {}", &file_or_dir_name, e),
+            },
+            Err(e) => error!("Opening send file: {}", e),
+        };
+        show_estimates(&api, &estimates).await;
+    }
+
+    /// Upload several PDF files as one letter, in the given order
+    pub async fn job_set_merged(&mut self, file_names: &[&str], args: &SetArgs) {
+        if let Err(e) = self.set_merged(file_names, args).await {
+            error!("Job {} not sent: {}", file_names.join(" + "), e);
+        }
+    }
+
+    async fn set_merged<P: AsRef<Path>>(&mut self, file_names: &[P], args: &SetArgs) -> Result<(), String> {
+        let (address, pdf) = merge_files(file_names)?;
+        let (api, ledger, archive_dir) = self.uploader(args);
+        let files: Vec<PathBuf> = file_names.iter().map(|name| name.as_ref().into()).collect();
+        let r = upload_letter(&api, &ledger, &archive_dir, &address, pdf, &files, None, args).await?;
+        info!("  Job {} sent{}, merged from {} files", &address, sent_to(&r), file_names.len());
+        Ok(())
+    }
+
+    /// Estimate the cost of several PDF files sent as one letter
+    pub async fn job_estimate_merged(&mut self, file_names: &[&str], args: &SetArgs) {
+        let api = self.api();
+        let estimate = merge_files(file_names).and_then(|(address, pdf)| {
+            api.estimate_job_from_bytes(&address, &pdf, &args.color, &args.mode, &args.ship)
+                .map(|estimate| (address, estimate))
+                .map_err(|e| e.to_string())
+        });
+        match estimate {
+            Ok(estimate) => show_estimates(&api, &[estimate]).await,
+            Err(e) => error!("Job {} not estimated: {}", file_names.join(" + "), e),
+        }
+    }
+
+    pub async fn watch_dir(&mut self, dir_name: &Path, args: &SetArgs) {
         debug!("Watch directory '{:#?}' for new PDF files", &dir_name);
-        let watch_dir = std::path::Path::new(&dir_name);
-        match fs::create_dir_all(&watch_dir) {
+        let watch_dir = dir_name;
+        match fs::create_dir_all(watch_dir) {
             Ok(_) => (),
             Err(e) => error!("Could not create watch_dir {:#?}, error {}", &watch_dir, e),
         }
+        let sandbox = self.api().is_sandbox();
+        claim_watch_dir(watch_dir, sandbox);
+        if sandbox {
+            info!("Watching {:?} with the test environment, letters are not printed", watch_dir);
+        }
 
         let (tx, rx) = channel();
 
@@ -309,7 +937,7 @@ impl LxpCommands {
         let mut watcher = watcher(tx, Duration::from_secs(10)).unwrap();
     
         // Add a path to be watched and monitored for changes.
-        match watcher.watch(&dir_name, RecursiveMode::NonRecursive) {
+        match watcher.watch(dir_name, RecursiveMode::NonRecursive) {
             Ok(_) => (),
             Err(e) => error!("Couldn't watch '{:#?}', error {}", &dir_name, e)
         };
@@ -319,13 +947,10 @@ impl LxpCommands {
                 Ok(event) => {
                     match event {
                         notify::DebouncedEvent::Create(pb) => {
-                            match pb.extension() {
-                                Some(ext) => if ext.to_ascii_lowercase() == "pdf" {
-                                    Some(pb)
-                                } else {
-                                    None
-                                },
-                                None => None, 
+                            if has_pdf_extension(&pb) || is_letter_dir(&pb) {
+                                Some(pb)
+                            } else {
+                                None
                             }
                         },
                        _ => None,
@@ -337,24 +962,346 @@ impl LxpCommands {
                 },
             };
 
-            match pdf_path {
-                Some(from_path) => {
-                    // push pdf file to print service
-                    match self.api().set_job(from_path.to_str().unwrap(), &color, &mode, &ship).await {
-                        Ok(_r) => info!("File {:#?} sent", &from_path),
-                        Err(_) => (), // Error message was already issued by set_job()
+            if let Some(from_path) = pdf_path {
+                // push pdf file to print service
+                let file_name = from_path.to_str().unwrap();
+                if from_path.is_dir() {
+                    // the files of a subdirectory are one letter
+                    let file_names = pdf_files(file_name);
+                    match self.set_merged(&file_names, args).await {
+                        Ok(()) => info!("Directory {:#?} sent", &from_path),
+                        // the daemon must keep running
+                        Err(e) => warn!("Directory {:#?} not sent: {}", &from_path, e),
+                    }
+                } else if let Some(split) = &args.split {
+                    match self.set_split_file(&from_path, split, args).await {
+                        Ok(()) => info!("File {:#?} sent", &from_path),
+                        // the daemon must keep running
+                        Err(e) => warn!("File {:#?} not sent: {}", &from_path, e),
+                    }
+                } else {
+                    let (api, ledger, archive_dir) = self.uploader(args);
+                    match upload_file(&api, &ledger, &archive_dir, &from_path, args).await {
+                        Ok(r) => info!("File {:#?} sent{}", &from_path, sent_to(&r)),
+                        // the daemon must keep running
+                        Err(e) => warn!("File {:#?} not sent: {}", &from_path, e),
                     }
+                }
 
-                    // move pdf filt to sent directory
-                    let file_name = from_path.file_name().unwrap();
-                    let to_path = from_path.parent().unwrap().join("sent").join(&file_name);
-                    match fs::rename(&from_path, &to_path) {
-                        Ok(_) => trace!("Move {:#?} to directory sent", &from_path),
-                        Err(e) => error!("Could not move PDF file {}", e),
-                    };
-                },
-                None => (),
+                // move pdf filt to sent directory
+                let to_path = sent_path(&from_path);
+                match fs::rename(&from_path, &to_path) {
+                    Ok(_) => trace!("Move {:#?} to {:#?}", &from_path, &to_path),
+                    // the daemon must keep running
+                    Err(e) => warn!("Could not move {:#?} to directory sent: {}", &from_path, e),
+                };
             }
         }
     }
 }
+
+// The recipient for the upload summary, if the address window was read
+fn sent_to(job: &lxptypes::JobCreated) -> String {
+    match job.recipient.is_empty() {
+        true => String::new(),
+        false => format!(" to {}", job.recipient.join(", ")),
+    }
+}
+
+// A watched directory serves either the test environment or real letters,
+// the first daemon decides. The sent directory and the log would mix up
+// both otherwise.
+fn claim_watch_dir(dir: &Path, sandbox: bool) {
+    let mode_path = dir.join("lxp.mode");
+    let mode = if sandbox { "sandbox" } else { "production" };
+    match fs::read_to_string(&mode_path) {
+        Ok(claimed) if claimed.trim() != mode => error!(
+            "Directory {:?} is watched with a {} profile, refusing to watch it with a {} profile",
+            dir,
+            claimed.trim(),
+            mode
+        ), // exits app
+        Ok(_) => (),
+        Err(_) => {
+            if let Err(e) = fs::write(&mode_path, mode) {
+                error!("Could not write {:?}: {}", mode_path, e);
+            }
+        }
+    }
+}
+
+// Print estimated costs and compare them with the credit balance
+async fn show_estimates(api: &LxpApi, estimates: &[(String, lxpapi::Estimate)]) {
+    if estimates.is_empty() {
+        info!("<No data>");
+        return;
+    }
+    info!("\n{:>6} {:>6} {:>8} {:>8} {:<35}", "Pages", "Sheets", "Cost", "Total", "File");
+    let mut sum = Money::default();
+    for (file_name, estimate) in estimates {
+        sum += &estimate.total();
+        info!(
+            "{:>6} {:>6} {:>8} {:>8} {:<35}",
+            estimate.pages,
+            estimate.sheets,
+            estimate.cost,
+            estimate.total(),
+            file_name
+        );
+    }
+    info!("\nThe estimated sum of the costs is {}", sum);
+
+    match api.get_balance().await {
+        Ok(balance) => {
+            info!("Credit balance {}", &balance.value);
+            match sum.checked_sub(&balance.value) {
+                Ok(missing) if missing > Money::zero(missing.currency()) => {
+                    info!("The credit is insufficient, {} are missing", missing)
+                }
+                Ok(_) => info!("The credit is sufficient for all jobs"),
+                // The tariff is in euro, the balance in the currency of the account
+                Err(e) => warn!("Credit and costs not compared: {}", e),
+            }
+        }
+        Err(e) => error!("Error when getting balance {}", e),
+    }
+}
+
+// Merge PDF files into one letter named after the first file
+fn merge_files<P: AsRef<Path>>(file_names: &[P]) -> Result<(String, Vec<u8>), String> {
+    let mut pdfs = Vec::new();
+    for file_name in file_names {
+        let path = file_name.as_ref();
+        pdfs.push(fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?);
+    }
+    let pdfs: Vec<&[u8]> = pdfs.iter().map(|pdf| pdf.as_slice()).collect();
+    let merged = merge_pdfs(&pdfs).map_err(|e| match e {
+        MergeError::Pdf { index, reason } => {
+            format!("{}: {}", file_names[index].as_ref().display(), reason)
+        }
+        e => e.to_string(),
+    })?;
+    let address = file_names
+        .first()
+        .and_then(|path| path.as_ref().file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    Ok((address, merged))
+}
+
+// The PDF files of a directory in alphabetical order
+fn pdf_files(dir_name: &str) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir(dir_name) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && has_pdf_extension(path))
+            .collect(),
+        Err(e) => {
+            error!("Reading directory {}: {}", dir_name, e);
+            Vec::new()
+        }
+    };
+    paths.sort();
+    paths
+}
+
+// Cut a PDF file into letters, named after the file and their position
+fn split_file(path: &Path, split: &SplitArg) -> Result<Vec<(String, PdfPart)>, String> {
+    let pdf = fs::read(path).map_err(|e| e.to_string())?;
+    let rule = match split {
+        SplitArg::Pages(pages) => SplitRule::Pages(*pages),
+        SplitArg::Ranges => {
+            let ranges_path = path.with_extension("ranges");
+            let ranges = fs::read_to_string(&ranges_path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| parse_page_ranges(&text).map_err(|e| e.to_string()))
+                .map_err(|e| format!("{}: {}", ranges_path.display(), e))?;
+            SplitRule::Ranges(ranges)
+        }
+        SplitArg::Text(marker) => SplitRule::Separator(marker.clone()),
+        SplitArg::BlankPage => SplitRule::BlankPage,
+    };
+    let parts = split_pdf(&pdf, &rule).map_err(|e| e.to_string())?;
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    Ok(parts
+        .into_iter()
+        .enumerate()
+        .map(|(idx, part)| (format!("{}-{:03}.pdf", stem, idx + 1), part))
+        .collect())
+}
+
+// Keep a copy of an uploaded letter held in memory
+fn archive_letter(archive_dir: &Path, id: i32, pdf: &[u8]) {
+    let archived = fs::create_dir_all(archive_dir)
+        .and_then(|_| fs::write(archive_dir.join(format!("{}.pdf", id)), pdf));
+    if let Err(e) = archived {
+        warn!("Could not archive job {}: {}", id, e);
+    }
+}
+
+// The copy of a job is only needed as long as the job can be changed
+fn drop_archived(archive_dir: &Path, id: i32) {
+    let path = archive_dir.join(format!("{}.pdf", id));
+    if path.is_file() {
+        match fs::remove_file(&path) {
+            Ok(()) => trace!("Archived copy of job {} removed", id),
+            Err(e) => warn!("Could not remove the archived copy of job {}: {}", id, e),
+        }
+    }
+}
+
+// Upload a PDF file as letter named after the file
+async fn upload_file(
+    api: &LxpApi,
+    ledger: &Ledger,
+    archive_dir: &Path,
+    path: &Path,
+    args: &SetArgs,
+) -> Result<lxptypes::JobCreated, String> {
+    let pdf = fs::read(path).map_err(|source| {
+        LxpApiError::PdfFile {
+            path: path.into(),
+            source,
+        }
+        .to_string()
+    })?;
+    let address = path.file_name().unwrap_or_default().to_string_lossy();
+    upload_letter(api, ledger, archive_dir, &address, pdf, &[path.into()], None, args).await
+}
+
+// Upload a letter unless the ledger knows it as sent, note and archive it.
+// `part` are the pages of the file the letter was split from.
+#[allow(clippy::too_many_arguments)]
+async fn upload_letter(
+    api: &LxpApi,
+    ledger: &Ledger,
+    archive_dir: &Path,
+    address: &str,
+    pdf: Vec<u8>,
+    files: &[PathBuf],
+    part: Option<&RangeInclusive<u32>>,
+    args: &SetArgs,
+) -> Result<lxptypes::JobCreated, String> {
+    let pages = validate_pdf(&pdf).map(|info| info.pages);
+    let mut entry = LedgerEntry::upload(address, files, &pdf, *pages.as_ref().unwrap_or(&0));
+    if let Some(part) = part {
+        entry = entry.split_from(part);
+    }
+    // Invalid documents are rejected by the upload without asking the server
+    if let (false, Ok(_)) = (args.force, pages) {
+        ledger
+            .claim(api, &entry)
+            .await
+            .map_err(|reason| format!("{}, send it with set --force if intended", reason))?;
+    }
+    let r = match api
+        .set_job_from_bytes(address, pdf.clone(), &args.color, &args.mode, &args.ship, args.dispatch_date)
+        .await
+    {
+        Ok(r) => r,
+        Err(e) => {
+            ledger.release(&entry);
+            return Err(e.to_string());
+        }
+    };
+    entry.job_id = r.id;
+    entry.color = args.color.code();
+    entry.mode = args.mode.as_str().into();
+    entry.ship = args.ship.as_str().into();
+    entry.dispatch_date = args.dispatch_date.map(|date| date.format("%Y-%m-%d").to_string());
+    ledger.record(entry).await;
+    archive_letter(archive_dir, r.id, &pdf);
+    Ok(r)
+}
+
+// Explain a failed access check in terms of the profile settings
+fn diagnosis(error: &LxpApiError, url: &str) -> String {
+    match error {
+        LxpApiError::Unauthorized { .. } => {
+            "The web service rejected the credentials, check user name and api key".into()
+        }
+        LxpApiError::Forbidden { .. } => {
+            "The credentials are valid, but the account may not use the api, check it in the customer portal".into()
+        }
+        LxpApiError::NotFound { .. } => format!(
+            "The url '{}' has no api endpoints, it usually ends with '/v1/'",
+            url
+        ),
+        LxpApiError::Http { status, .. } if status.as_u16() == 404 => format!(
+            "The url '{}' has no api endpoints, it usually ends with '/v1/'",
+            url
+        ),
+        LxpApiError::Json { .. } => format!(
+            "The url '{}' answers, but not like the LetterXpress web service, check the url",
+            url
+        ),
+        LxpApiError::Connection { .. } => {
+            "Fix the proxy and certificate settings of the profile".into()
+        }
+        LxpApiError::Transport(source) => {
+            if source.is_timeout() {
+                "The web service did not answer in time, check the network or raise the timeouts".into()
+            } else if is_certificate_error(source) {
+                "The TLS certificate of the server is not trusted, add the CA certificate to the profile".into()
+            } else if source.is_connect() {
+                format!(
+                    "Could not connect to '{}', check the url, the network and the proxy settings",
+                    url
+                )
+            } else {
+                "Run the command with -vv to see the requests".into()
+            }
+        }
+        _ => "Run the command with -vv to see the requests".into(),
+    }
+}
+
+// Place in the sent directory for a file or directory of the watched
+// directory, a name that is taken gets a number
+fn sent_path(from_path: &Path) -> PathBuf {
+    let sent_dir = from_path.parent().unwrap().join("sent");
+    let file_name = from_path.file_name().unwrap();
+    let mut to_path = sent_dir.join(file_name);
+    let stem = from_path.file_stem().unwrap_or(file_name).to_string_lossy();
+    let mut count = 1;
+    while to_path.exists() {
+        let name = match from_path.extension() {
+            Some(ext) if from_path.is_file() => format!("{}-{}.{}", stem, count, ext.to_string_lossy()),
+            _ => format!("{}-{}", file_name.to_string_lossy(), count),
+        };
+        to_path = sent_dir.join(name);
+        count += 1;
+    }
+    to_path
+}
+
+// Does a TLS error in the chain of causes complain about the certificate?
+fn is_certificate_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut cause = Some(error);
+    while let Some(e) = cause {
+        if e.to_string().to_lowercase().contains("certificate") {
+            return true;
+        }
+        cause = e.source();
+    }
+    false
+}
+
+// Subdirectories of a watched directory hold the files of one letter
+fn is_letter_dir(path: &Path) -> bool {
+    path.is_dir() && path.file_name().is_some_and(|name| name != "sent")
+}
+
+// Candidates for uploading in directories, the content is checked by LxpApi
+fn has_pdf_extension(path: &Path) -> bool {
+    match path.extension() {
+        Some(ext) => ext.eq_ignore_ascii_case("pdf"),
+        None => false,
+    }
+}
+
+// Sent and deleted jobs do not change anymore, unknown jobs are not listed
+// by the server anymore
+fn is_final(status: &str) -> bool {
+    status == "sent" || status == "deleted" || status == "unknown"
+}
@@ -3,6 +3,7 @@
 // This simplifies the interface design to the library.
 
 use log::*;
+use lxpapi::{AddressCheck, AddressWindow, Tariff};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
@@ -14,6 +15,52 @@ pub struct Profile {
     pub user_name: String,
     pub url: String,
     pub api_key: String,
+    /// Number of attempts for a request, 1 disables retries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_attempts: Option<u32>,
+    /// Number of requests running at the same time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+    /// Requests started per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_second: Option<f64>,
+    /// Connect timeout in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u64>,
+    /// Timeout of a whole request in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_timeout: Option<u64>,
+    /// Proxy for all requests, e.g. "http://proxy.local:3128"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_password: Option<String>,
+    /// Additional trusted CA certificate file, PEM or DER encoded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_certificate: Option<PathBuf>,
+    /// Accept any server certificate, only meant for tests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accept_invalid_certs: Option<bool>,
+    /// Send the requests to the test environment instead of `url`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<bool>,
+    /// Url of the test environment, the one of LetterXpress if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox_url: Option<String>,
+    /// Days in which a letter with the same content is not sent again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_window_days: Option<i64>,
+    /// Handling of letters whose address window is empty or overflows
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address_check: Option<AddressCheck>,
+    /// Prices for cost estimates, the library defaults if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tariff: Option<Tariff>,
+    /// Position of the address window, DIN 5008 form B if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address_window: Option<AddressWindow>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -40,12 +87,7 @@ impl LxpConfig {
         let mut lxp_config = LxpConfig::default();
 
         let profiles = match fs::read_to_string(&config_path) {
-            Ok(s) => {
-                match toml::from_str::<Profiles>(&s) {
-                    Ok(profiles) => profiles,
-                    Err(_) => Profiles::default(),
-                }
-            },
+            Ok(s) => toml::from_str::<Profiles>(&s).unwrap_or_default(),
             Err(_) => Profiles::default(),
         };
 
@@ -74,6 +116,21 @@ impl LxpConfig {
         }
     }
 
+    /// Copies of the uploaded letters of the active profile, they are needed
+    /// to change the options of jobs
+    pub fn archive_dir(&self) -> PathBuf {
+        let profile_name = self.get_active_profile_name().unwrap_or_default();
+        self.config_path
+            .parent()
+            .unwrap() // the config file is always inside the config dir
+            .join("archive")
+            .join(profile_name)
+    }
+
+    pub fn get_profile(&self, profile_name: &str) -> Option<Profile> {
+        self.profiles.profiles.get(profile_name).cloned()
+    }
+
     pub fn get_active_profile_name(&self) -> Option<String> {
         self.profiles.profile_active.clone()
     }
@@ -93,7 +150,7 @@ impl LxpConfig {
     pub fn delete_profile(&mut self, profile_name: &str) {
         match self.profiles.profiles.remove(profile_name) {
             Some(_p) => {
-                match self.profiles.profiles.keys().cloned().next() {
+                match self.profiles.profiles.keys().next().cloned() {
                     Some(pnew) => {
                         info!(
                             "Profile {} deleted, profile {} activated",
@@ -133,10 +190,18 @@ impl LxpConfig {
         }
         info!("\n{:<15} {:<30} {}", "<profile>", "<user>", "<url>");
         for (profile_name, profile) in &self.profiles.profiles {
-            info!(
-                "{:<15} {:<30} {}",
-                profile_name, profile.user_name, profile.url
-            );
+            match profile.sandbox {
+                Some(true) => info!(
+                    "{:<15} {:<30} {} (sandbox)",
+                    profile_name,
+                    profile.user_name,
+                    profile.sandbox_url.as_deref().unwrap_or(lxpapi::SANDBOX_URL)
+                ),
+                _ => info!(
+                    "{:<15} {:<30} {}",
+                    profile_name, profile.user_name, profile.url
+                ),
+            }
         }
     }
 }
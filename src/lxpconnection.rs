@@ -0,0 +1,102 @@
+/// Connection settings of LxpApi
+///
+/// Networks that only allow outbound traffic through a proxy, possibly with
+/// an internal certificate authority breaking up TLS, need some settings for
+/// the HTTP client. Without them the client connects directly, honours the
+/// usual proxy environment variables and trusts the system certificates.
+use crate::lxperror::LxpApiError;
+use crate::lxplimits::Limits;
+
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Connection {
+    /// Proxy for all requests, e.g. "http://proxy.local:3128"
+    pub proxy: Option<String>,
+    /// User name, if the proxy requires authentication
+    pub proxy_user: Option<String>,
+    pub proxy_password: Option<String>,
+    /// Additional trusted CA certificates, PEM or DER encoded
+    pub ca_certificates: Vec<PathBuf>,
+    /// Accept any server certificate, only meant for tests
+    pub accept_invalid_certs: bool,
+}
+
+// Connection settings checked and loaded, ready to build clients from
+#[derive(Clone, Default)]
+pub(crate) struct ClientConfig {
+    proxy: Option<reqwest::Proxy>,
+    certificates: Vec<reqwest::Certificate>,
+    accept_invalid_certs: bool,
+}
+
+// reqwest::Certificate has no Debug
+impl fmt::Debug for ClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientConfig")
+            .field("proxy", &self.proxy)
+            .field("certificates", &self.certificates.len())
+            .field("accept_invalid_certs", &self.accept_invalid_certs)
+            .finish()
+    }
+}
+
+fn connection_error(message: String) -> LxpApiError {
+    LxpApiError::Connection { message }
+}
+
+impl ClientConfig {
+    pub(crate) fn new(connection: &Connection) -> Result<ClientConfig, LxpApiError> {
+        let proxy = match &connection.proxy {
+            Some(url) => {
+                let proxy = reqwest::Proxy::all(url.as_str())
+                    .map_err(|e| connection_error(format!("proxy '{}': {}", url, e)))?;
+                match &connection.proxy_user {
+                    Some(user) => Some(proxy.basic_auth(
+                        user,
+                        connection.proxy_password.as_deref().unwrap_or_default(),
+                    )),
+                    None => Some(proxy),
+                }
+            }
+            None => None,
+        };
+
+        let mut certificates = Vec::new();
+        for path in &connection.ca_certificates {
+            let content = std::fs::read(path)
+                .map_err(|e| connection_error(format!("CA certificate {:?}: {}", path, e)))?;
+            let certificate = if content.starts_with(b"-----BEGIN") {
+                reqwest::Certificate::from_pem(&content)
+            } else {
+                reqwest::Certificate::from_der(&content)
+            };
+            certificates.push(
+                certificate
+                    .map_err(|e| connection_error(format!("CA certificate {:?}: {}", path, e)))?,
+            );
+        }
+
+        Ok(ClientConfig {
+            proxy,
+            certificates,
+            accept_invalid_certs: connection.accept_invalid_certs,
+        })
+    }
+}
+
+pub(crate) fn build_client(limits: &Limits, config: &ClientConfig) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(limits.connect_timeout)
+        .timeout(limits.request_timeout)
+        .danger_accept_invalid_certs(config.accept_invalid_certs);
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(proxy.clone());
+    }
+    for certificate in &config.certificates {
+        builder = builder.add_root_certificate(certificate.clone());
+    }
+    // Fails only if the TLS backend can't be initialized, like Client::new
+    builder.build().expect("Could not create HTTP client")
+}
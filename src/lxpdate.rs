@@ -0,0 +1,213 @@
+/// Dates and times of the LetterXpress web service
+///
+/// The web service sends local German time without an offset, e.g.
+/// "2020-12-10 09:45:31". It is converted to UTC using the rules of
+/// Europe/Berlin (CET, CEST from the last Sunday in March to the last Sunday
+/// in October). Dates without time, like invoice dates, stay naive dates.
+use crate::lxperror::LxpApiError;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Utc, Weekday};
+use serde::{Deserialize, Deserializer, Serializer};
+
+const DATE_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+fn last_sunday(year: i32, month: u32) -> NaiveDate {
+    let mut day = NaiveDate::from_ymd(year, month + 1, 1).pred();
+    while day.weekday() != Weekday::Sun {
+        day = day.pred();
+    }
+    day
+}
+
+fn is_summer_time(utc: NaiveDateTime) -> bool {
+    let begin = last_sunday(utc.year(), 3).and_hms(1, 0, 0);
+    let end = last_sunday(utc.year(), 10).and_hms(1, 0, 0);
+    utc >= begin && utc < end
+}
+
+/// Convert a time of the web service to UTC
+pub fn server_time_to_utc(local: NaiveDateTime) -> DateTime<Utc> {
+    let summer = local - Duration::hours(2);
+    let utc = if is_summer_time(summer) {
+        summer
+    } else {
+        local - Duration::hours(1)
+    };
+    DateTime::from_utc(utc, Utc)
+}
+
+/// Convert UTC to the time of the web service
+pub fn utc_to_server_time(utc: DateTime<Utc>) -> NaiveDateTime {
+    let utc = utc.naive_utc();
+    match is_summer_time(utc) {
+        true => utc + Duration::hours(2),
+        false => utc + Duration::hours(1),
+    }
+}
+
+/// A dispatch date must be a day after the current day of the web service
+pub fn validate_dispatch_date(date: NaiveDate) -> Result<NaiveDate, LxpApiError> {
+    let today = utc_to_server_time(Utc::now()).date();
+    if date > today {
+        Ok(date)
+    } else {
+        Err(LxpApiError::DispatchDate { date })
+    }
+}
+
+fn parse_date_time(s: &str) -> Result<DateTime<Utc>, String> {
+    NaiveDateTime::parse_from_str(s.trim(), DATE_TIME_FORMAT)
+        .map(server_time_to_utc)
+        .map_err(|e| format!("invalid date and time '{}': {}", s, e))
+}
+
+// Dates are sometimes sent with a time of day, which is ignored
+fn parse_date(s: &str) -> Result<NaiveDate, String> {
+    let s = s.trim();
+    NaiveDate::parse_from_str(s.get(..10).unwrap_or(s), DATE_FORMAT)
+        .map_err(|e| format!("invalid date '{}': {}", s, e))
+}
+
+// Missing dates are sent as null, empty string or zeros
+fn is_unset(s: &str) -> bool {
+    s.trim().is_empty() || s.starts_with("0000-00-00")
+}
+
+pub mod date_time {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(date: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&utc_to_server_time(*date).format(DATE_TIME_FORMAT).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<DateTime<Utc>, D::Error> {
+        parse_date_time(&String::deserialize(d)?).map_err(serde::de::Error::custom)
+    }
+}
+
+pub mod option_date_time {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        date: &Option<DateTime<Utc>>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        match date {
+            Some(date) => super::date_time::serialize(date, s),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Option<DateTime<Utc>>, D::Error> {
+        match Option::<String>::deserialize(d)? {
+            Some(date) if !is_unset(&date) => parse_date_time(&date)
+                .map(Some)
+                .map_err(serde::de::Error::custom),
+            _ => Ok(None),
+        }
+    }
+}
+
+pub mod date {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(date: &NaiveDate, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&date.format(DATE_FORMAT).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<NaiveDate, D::Error> {
+        parse_date(&String::deserialize(d)?).map_err(serde::de::Error::custom)
+    }
+}
+
+pub mod option_date {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(date: &Option<NaiveDate>, s: S) -> Result<S::Ok, S::Error> {
+        match date {
+            Some(date) => super::date::serialize(date, s),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<NaiveDate>, D::Error> {
+        match Option::<String>::deserialize(d)? {
+            Some(date) if !is_unset(&date) => parse_date(&date)
+                .map(Some)
+                .map_err(serde::de::Error::custom),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::from_utc(NaiveDateTime::parse_from_str(s, DATE_TIME_FORMAT).unwrap(), Utc)
+    }
+
+    #[test]
+    fn winter_and_summer_time() {
+        assert_eq!(
+            parse_date_time("2020-12-10 09:45:31").unwrap(),
+            utc("2020-12-10 08:45:31")
+        );
+        assert_eq!(
+            parse_date_time("2020-07-01 12:00:00").unwrap(),
+            utc("2020-07-01 10:00:00")
+        );
+    }
+
+    #[test]
+    fn switching_days() {
+        // 2020-03-29 02:00 CET -> 03:00 CEST, 2020-10-25 03:00 CEST -> 02:00 CET
+        assert_eq!(
+            parse_date_time("2020-03-29 01:59:00").unwrap(),
+            utc("2020-03-29 00:59:00")
+        );
+        assert_eq!(
+            parse_date_time("2020-03-29 03:00:00").unwrap(),
+            utc("2020-03-29 01:00:00")
+        );
+        assert_eq!(
+            parse_date_time("2020-10-25 01:30:00").unwrap(),
+            utc("2020-10-24 23:30:00")
+        );
+        assert_eq!(
+            parse_date_time("2020-10-25 03:30:00").unwrap(),
+            utc("2020-10-25 02:30:00")
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let date = utc("2020-06-30 22:15:00");
+        assert_eq!(
+            server_time_to_utc(utc_to_server_time(date)),
+            date
+        );
+    }
+
+    #[test]
+    fn dispatch_date_in_the_future() {
+        let today = utc_to_server_time(Utc::now()).date();
+        assert!(validate_dispatch_date(today.succ()).is_ok());
+        assert!(validate_dispatch_date(today).is_err());
+        assert!(validate_dispatch_date(today.pred()).is_err());
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse_date("2020-10-31").unwrap(), NaiveDate::from_ymd(2020, 10, 31));
+        assert_eq!(
+            parse_date("2020-10-31 00:00:00").unwrap(),
+            NaiveDate::from_ymd(2020, 10, 31)
+        );
+        assert!(parse_date("31.10.2020").is_err());
+        assert!(is_unset("0000-00-00 00:00:00"));
+    }
+}
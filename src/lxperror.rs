@@ -0,0 +1,203 @@
+/// Error type of LxpApi
+///
+/// Every error carries the underlying cause, so that the caller can decide
+/// how to react. Well-known status codes of the LetterXpress web service are
+/// mapped to their own variants, all others end up in `Service`.
+use crate::lxpaddress::AddressProblem;
+use crate::lxppdf::PdfError;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+// Maximum number of characters of a response body kept in an error
+const BODY_SNIPPET_LEN: usize = 200;
+
+#[derive(Debug)]
+pub enum LxpApiError {
+    /// The PDF file could not be opened or read
+    PdfFile { path: PathBuf, source: io::Error },
+    /// The document did not pass the local PDF preflight
+    InvalidPdf { name: String, reason: PdfError },
+    /// The address window of the first page failed the preflight
+    Address { name: String, problem: AddressProblem },
+    /// The request did not reach the web service or no answer was received
+    Transport(reqwest::Error),
+    /// The web service answered with an HTTP error and without a usable body
+    Http { status: reqwest::StatusCode, body: String },
+    /// Status 400, the request was not accepted
+    BadRequest { message: String },
+    /// Status 401, user name or api key are wrong
+    Unauthorized { message: String },
+    /// Status 403, the account is not allowed to do this
+    Forbidden { message: String },
+    /// Status 404, the requested job or invoice does not exist
+    NotFound { message: String },
+    /// Status 409, the request conflicts with the state of a job
+    Conflict { message: String },
+    /// Any other status reported by the web service
+    Service { status: i32, message: String },
+    /// The answer of the web service could not be decoded
+    Json { source: serde_json::Error, body: String },
+    /// The answer of the web service is JSON, but its content is unexpected
+    Invalid { message: String },
+    /// The letter can't be scheduled for this day
+    DispatchDate { date: chrono::NaiveDate },
+    /// Proxy or certificate settings are not usable
+    Connection { message: String },
+    /// The web service does not offer the operation
+    Unsupported { message: String },
+    /// A job was deleted to be uploaded again with changed options, but the
+    /// upload failed
+    Resubmit { id: i32, source: Box<LxpApiError> },
+    /// Uploading failed in a way that the letter may have been accepted
+    /// nevertheless; `job_ids` are the jobs like the letter found on the
+    /// server, it may be empty if the server did not answer at all
+    Unconfirmed { job_ids: Vec<i32>, source: Box<LxpApiError> },
+}
+
+impl LxpApiError {
+    /// Map a status code of the web service to an error
+    pub fn from_status(status: i32, message: &str) -> LxpApiError {
+        let message = message.to_string();
+        match status {
+            400 => LxpApiError::BadRequest { message },
+            401 => LxpApiError::Unauthorized { message },
+            403 => LxpApiError::Forbidden { message },
+            404 => LxpApiError::NotFound { message },
+            409 => LxpApiError::Conflict { message },
+            _ => LxpApiError::Service { status, message },
+        }
+    }
+
+    pub(crate) fn json(source: serde_json::Error, body: &str) -> LxpApiError {
+        LxpApiError::Json {
+            source,
+            body: snippet(body),
+        }
+    }
+
+    pub(crate) fn invalid(message: &str) -> LxpApiError {
+        LxpApiError::Invalid {
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn http(status: reqwest::StatusCode, body: &str) -> LxpApiError {
+        LxpApiError::Http {
+            status,
+            body: snippet(body),
+        }
+    }
+
+    /// Status code reported by the web service, if there is one
+    pub fn status(&self) -> Option<i32> {
+        match self {
+            LxpApiError::Http { status, .. } => Some(status.as_u16() as i32),
+            LxpApiError::BadRequest { .. } => Some(400),
+            LxpApiError::Unauthorized { .. } => Some(401),
+            LxpApiError::Forbidden { .. } => Some(403),
+            LxpApiError::NotFound { .. } => Some(404),
+            LxpApiError::Conflict { .. } => Some(409),
+            LxpApiError::Service { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+// Shorten a response body for error messages, respecting char boundaries
+fn snippet(body: &str) -> String {
+    match body.char_indices().nth(BODY_SNIPPET_LEN) {
+        Some((idx, _)) => format!("{}...", &body[..idx]),
+        None => body.to_string(),
+    }
+}
+
+// user-facing output
+impl fmt::Display for LxpApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LxpApiError::PdfFile { path, source } => {
+                write!(f, "Couldn't read PDF file {:?}: {}", path, source)
+            }
+            LxpApiError::InvalidPdf { name, reason } => {
+                write!(f, "No valid PDF document '{}': {}", name, reason)
+            }
+            LxpApiError::Address { name, problem } => {
+                write!(f, "Address check of '{}' failed: {}", name, problem)
+            }
+            LxpApiError::Transport(e) => {
+                write!(f, "Web service not reachable, check url and network: {}", e)
+            }
+            LxpApiError::Http { status, body } => {
+                write!(f, "Web service answered with HTTP status {} '{}'", status, body)
+            }
+            LxpApiError::BadRequest { message } => {
+                write!(f, "Web service rejected the request (400): {}", message)
+            }
+            LxpApiError::Unauthorized { message } => {
+                write!(f, "Web service refused access, check user and apikey (401): {}", message)
+            }
+            LxpApiError::Forbidden { message } => {
+                write!(f, "Web service denied the operation (403): {}", message)
+            }
+            LxpApiError::NotFound { message } => write!(f, "Not found (404): {}", message),
+            LxpApiError::Conflict { message } => {
+                write!(f, "Operation not possible in the current job state (409): {}", message)
+            }
+            LxpApiError::Service { status, message } => {
+                write!(f, "Web service reported status {}: {}", status, message)
+            }
+            LxpApiError::Json { source, body } => {
+                write!(f, "Unexpected answer from web service ({}): '{}'", source, body)
+            }
+            LxpApiError::Invalid { message } => {
+                write!(f, "Unexpected content in answer from web service: {}", message)
+            }
+            LxpApiError::DispatchDate { date } => {
+                write!(f, "Dispatch date {} is not in the future", date.format("%Y-%m-%d"))
+            }
+            LxpApiError::Connection { message } => {
+                write!(f, "Invalid connection settings: {}", message)
+            }
+            LxpApiError::Unsupported { message } => {
+                write!(f, "Not supported by the web service: {}", message)
+            }
+            LxpApiError::Resubmit { id, source } => write!(
+                f,
+                "Job {} was deleted, but uploading it again failed ({}), upload it manually",
+                id, source
+            ),
+            LxpApiError::Unconfirmed { job_ids, source } if job_ids.is_empty() => write!(
+                f,
+                "Upload not confirmed ({}), check the job lists before sending again",
+                source
+            ),
+            LxpApiError::Unconfirmed { job_ids, source } => write!(
+                f,
+                "Upload not confirmed ({}), check job(s) {} before sending again",
+                source,
+                job_ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+}
+
+impl error::Error for LxpApiError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            LxpApiError::PdfFile { source, .. } => Some(source),
+            LxpApiError::Transport(e) => Some(e),
+            LxpApiError::Json { source, .. } => Some(source),
+            LxpApiError::Resubmit { source, .. } => Some(source.as_ref()),
+            LxpApiError::Unconfirmed { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for LxpApiError {
+    fn from(e: reqwest::Error) -> LxpApiError {
+        LxpApiError::Transport(e)
+    }
+}
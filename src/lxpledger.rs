@@ -0,0 +1,558 @@
+// Ledger of the uploaded letters of a profile, kept next to the archived
+// copies. Running `lxp set pdf_dir` twice must not send the same invoices
+// again, so the checksum of every letter sent is noted with its job id.
+// A letter is a duplicate if its checksum was sent within the window of the
+// profile, or if a job with the same content or the same name and page count
+// is still waiting on the server.
+//
+// The ledger is the permanent job history as well. The server lists sent
+// jobs only for some days, so `lxp sync` takes over the state of the jobs
+// while they are listed; the entries stay after they are gone on the server.
+//
+// Several lxp processes may use the ledger at the same time, e.g. a cron job
+// running `lxp sync` and a watch-dir daemon. Each one writes only the entries
+// it changed, merged into the file as it is on disk, while holding a lock
+// file.
+
+use chrono::{DateTime, Duration, Local, Utc};
+use log::*;
+use lxpapi::lxptypes::{ColorPrint, Job, Mode, Ship};
+use lxpapi::{LxpApi, LxpApiError, JOB_LOOKUP_DAYS};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration as StdDuration, Instant};
+
+// Days a checksum blocks another upload if the profile does not tell
+pub const DEFAULT_WINDOW_DAYS: i64 = 30;
+
+// Status of jobs the server deleted
+const DELETED: &str = "deleted";
+// Status of jobs the server does not list anymore, they were either sent
+// long ago or deleted
+const UNKNOWN: &str = "unknown";
+
+// A lock file older than this was left by a process that died
+const STALE_LOCK: StdDuration = StdDuration::from_secs(60);
+// Time to wait for another process writing the ledger
+const LOCK_TIMEOUT: StdDuration = StdDuration::from_secs(10);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub job_id: i32,
+    pub address: String,
+    /// Files the letter was made of, empty if it was uploaded elsewhere
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+    /// Pages of the file the letter was split from, e.g. "3-4"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub part: Option<String>,
+    /// Checksum of the letter, empty if it was uploaded elsewhere
+    #[serde(default)]
+    pub checksum: String,
+    #[serde(default)]
+    pub pages: u32,
+    /// Color code, mode and shipping as named by the web service
+    #[serde(default)]
+    pub color: i32,
+    #[serde(default)]
+    pub mode: String,
+    #[serde(default)]
+    pub ship: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dispatch_date: Option<String>,
+    /// Time of the upload, RFC 3339
+    #[serde(alias = "sent")]
+    pub uploaded: String,
+    /// Status on the server at the last sync, "deleted" if it was deleted,
+    /// "unknown" if the server does not list it anymore
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<String>,
+    /// Day the letter was handed over to the post
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_date: Option<String>,
+    /// Time of the last sync, RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synced: Option<String>,
+}
+
+impl LedgerEntry {
+    /// A new letter, the job id is set after the upload
+    pub fn upload(address: &str, files: &[PathBuf], pdf: &[u8], pages: u32) -> LedgerEntry {
+        LedgerEntry {
+            address: address.into(),
+            files: files
+                .iter()
+                .map(|file| fs::canonicalize(file).unwrap_or_else(|_| file.clone()))
+                .collect(),
+            checksum: checksum(pdf),
+            pages,
+            uploaded: Utc::now().to_rfc3339(),
+            ..Default::default()
+        }
+    }
+
+    /// The letter is the part with the given pages of its file
+    pub fn split_from(mut self, pages: &RangeInclusive<u32>) -> LedgerEntry {
+        self.part = Some(format!("{}-{}", pages.start(), pages.end()));
+        self
+    }
+
+    // Letters with the same content are the same letter, unless they are
+    // different parts of one file, e.g. the same form for two recipients
+    fn same_letter(&self, other: &LedgerEntry) -> bool {
+        let other_part = self.part.is_some()
+            && other.part.is_some()
+            && self.part != other.part
+            && self.files == other.files;
+        self.checksum == other.checksum && !other_part
+    }
+
+    fn uploaded(&self) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(&self.uploaded)
+            .map(|uploaded| uploaded.with_timezone(&Utc))
+            .unwrap_or_else(|_| Utc::now())
+    }
+
+    // Take over the state of the job on the server, fails if its costs can't
+    // be summed up
+    fn update(&mut self, job: &Job) -> Result<(), String> {
+        let cost = job.cost.checked_add(&job.cost_vat)?;
+        self.address = job.address.clone();
+        self.pages = job.pages;
+        self.color = job.color.code();
+        self.mode = job.mode.as_str().into();
+        self.ship = job.shipping.as_str().into();
+        self.dispatch_date = job.dispatchdate.map(|date| date.format("%Y-%m-%d").to_string());
+        self.status = Some(job.status.as_str().into());
+        self.cost = Some(cost.to_string());
+        self.sent_date = job.sentdate.map(|date| date.format("%Y-%m-%d").to_string());
+        self.synced = Some(Utc::now().to_rfc3339());
+        Ok(())
+    }
+
+    // Last time the job was seen on the server, or uploaded from here
+    fn seen(&self) -> DateTime<Utc> {
+        self.synced
+            .as_deref()
+            .and_then(|synced| DateTime::parse_from_rfc3339(synced).ok())
+            .map(|synced| synced.with_timezone(&Utc))
+            .unwrap_or_else(|| self.uploaded())
+    }
+
+    // Sent, deleted and unknown jobs do not change anymore
+    fn is_final(&self) -> bool {
+        matches!(self.status.as_deref(), Some("sent") | Some(DELETED) | Some(UNKNOWN))
+    }
+}
+
+/// What a sync changed in the ledger
+#[derive(Debug, Default)]
+pub struct SyncCount {
+    pub updated: usize,
+    pub added: usize,
+    pub deleted: usize,
+    pub unknown: usize,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Entries {
+    entries: Vec<LedgerEntry>,
+    // Letters being uploaded right now
+    #[serde(skip)]
+    uploading: Vec<LedgerEntry>,
+    // Job ids of the entries changed since the ledger was written
+    #[serde(skip)]
+    changed: HashSet<i32>,
+}
+
+/// Checksum identifying the content of a letter
+pub fn checksum(pdf: &[u8]) -> String {
+    format!("{:x}", md5::compute(pdf))
+}
+
+#[derive(Debug)]
+pub struct Ledger {
+    path: PathBuf,
+    entries: Arc<Mutex<Entries>>,
+    window: Duration,
+    // Jobs on hold or in the queue, fetched for the first letter
+    pending: tokio::sync::Mutex<Option<Vec<Job>>>,
+    // Ids of the jobs recorded through this ledger
+    uploaded: Arc<Mutex<Vec<i32>>>,
+}
+
+impl Ledger {
+    pub fn load(archive_dir: &Path, window_days: i64) -> Ledger {
+        let path = archive_dir.join("ledger.json");
+        let entries = match read_entries(&path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("{}", e); // exits app
+                unreachable!()
+            }
+        };
+        Ledger {
+            path,
+            entries: Arc::new(Mutex::new(Entries {
+                entries,
+                ..Default::default()
+            })),
+            window: Duration::days(window_days),
+            pending: tokio::sync::Mutex::new(None),
+            uploaded: Default::default(),
+        }
+    }
+
+    /// Collect the job ids of the letters recorded from now on in `uploaded`,
+    /// other processes may record letters in the same ledger file
+    pub fn collect_uploads(mut self, uploaded: Arc<Mutex<Vec<i32>>>) -> Ledger {
+        self.uploaded = uploaded;
+        self
+    }
+
+    /// Reserve a letter for uploading, fails with the reason if it is a
+    /// duplicate. The letter is either recorded or released afterwards.
+    pub async fn claim(&self, api: &LxpApi, letter: &LedgerEntry) -> Result<(), String> {
+        let mut pending = self.pending.lock().await;
+        if pending.is_none() {
+            *pending = Some(
+                pending_jobs(api)
+                    .await
+                    .map_err(|e| format!("could not look for duplicates on the server: {}", e))?,
+            );
+        }
+        let pending = pending.as_deref().unwrap_or_default();
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.uploading.iter().any(|other| other.same_letter(letter)) {
+            return Err("a letter with the same content is being sent".into());
+        }
+        if let Some(reason) = duplicate(&entries.entries, pending, self.window, letter) {
+            return Err(reason);
+        }
+        entries.uploading.push(letter.clone());
+        Ok(())
+    }
+
+    /// Give up a claimed letter that was not sent
+    pub fn release(&self, letter: &LedgerEntry) {
+        release(&mut self.entries.lock().unwrap(), letter);
+    }
+
+    /// Note an uploaded letter
+    pub async fn record(&self, entry: LedgerEntry) {
+        let job_id = entry.job_id;
+        {
+            let mut entries = self.entries.lock().unwrap();
+            release(&mut entries, &entry);
+            entries.entries.push(entry);
+            entries.changed.insert(job_id);
+        }
+        self.uploaded.lock().unwrap().push(job_id);
+        // Another process may hold the ledger, the other uploads go on meanwhile
+        let (path, entries) = (self.path.clone(), self.entries.clone());
+        let stored = tokio::task::spawn_blocking(move || store(&path, &entries))
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+        // The letter is sent, so a failure must not stop the others
+        if let Err(e) = stored {
+            warn!("Could not note job {} in the ledger: {}", job_id, e);
+        }
+    }
+
+    /// Take over the state of jobs listed by the server, unknown jobs are
+    /// added to the history
+    pub fn sync_jobs(&self, jobs: &[Job], count: &mut SyncCount) {
+        let mut entries = self.entries.lock().unwrap();
+        for job in jobs {
+            let updated = match entries.entries.iter_mut().rev().find(|entry| entry.job_id == job.id) {
+                Some(entry) => entry.update(job).map(|_| count.updated += 1),
+                None => {
+                    let mut entry = LedgerEntry {
+                        job_id: job.id,
+                        uploaded: job.date.to_rfc3339(),
+                        ..Default::default()
+                    };
+                    entry.update(job).map(|_| {
+                        entries.entries.push(entry);
+                        count.added += 1;
+                    })
+                }
+            };
+            match updated {
+                Ok(()) => {
+                    entries.changed.insert(job.id);
+                }
+                // keep going with the other jobs
+                Err(e) => warn!("Job {} not synchronized: {}", job.id, e),
+            }
+        }
+    }
+
+    /// Ids of the jobs that may still change on the server
+    pub fn open_jobs(&self) -> Vec<i32> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .entries
+            .iter()
+            .filter(|entry| !entry.is_final())
+            .map(|entry| entry.job_id)
+            .collect()
+    }
+
+    /// Note that a job was deleted
+    pub fn mark_deleted(&self, job_id: i32) {
+        self.change(job_id, |entry| {
+            entry.status = Some(DELETED.into());
+            entry.synced = Some(Utc::now().to_rfc3339());
+        });
+    }
+
+    /// Note that the server does not know a job anymore. Sent jobs are
+    /// listed for JOB_LOOKUP_DAYS, so a job seen since then was deleted;
+    /// otherwise it may have been sent long ago and its status is unknown.
+    /// Returns true if the job is noted as deleted, a job without entry is
+    /// unknown.
+    pub fn mark_missing(&self, job_id: i32) -> bool {
+        let lookup = Utc::now() - Duration::days(JOB_LOOKUP_DAYS.into());
+        let mut deleted = false;
+        self.change(job_id, |entry| {
+            deleted = entry.seen() > lookup;
+            entry.status = Some(if deleted { DELETED } else { UNKNOWN }.into());
+            entry.synced = Some(Utc::now().to_rfc3339());
+        });
+        deleted
+    }
+
+    /// Note the new print options of a job, it may have been replaced by a
+    /// new one with another id
+    pub fn update_job(&self, job_id: i32, new_id: i32, color: &ColorPrint, mode: &Mode, ship: &Ship) {
+        self.change(job_id, |entry| {
+            entry.job_id = new_id;
+            entry.color = color.code();
+            entry.mode = mode.as_str().into();
+            entry.ship = ship.as_str().into();
+        });
+    }
+
+    fn change<F: FnOnce(&mut LedgerEntry)>(&self, job_id: i32, f: F) {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.entries.iter_mut().rev().find(|entry| entry.job_id == job_id) {
+                Some(entry) => {
+                    f(entry);
+                    let new_id = entry.job_id;
+                    entries.changed.insert(job_id);
+                    entries.changed.insert(new_id);
+                }
+                None => return,
+            }
+        }
+        if let Err(e) = self.save() {
+            warn!("Could not note job {} in the ledger: {}", job_id, e);
+        }
+    }
+
+    /// Write the ledger after a sync
+    pub fn save(&self) -> Result<(), String> {
+        store(&self.path, &self.entries)
+    }
+}
+
+// Merge the changed entries into the ledger on disk, other processes may
+// have written it since it was read. The entries are only locked while
+// merging, not while waiting for the lock file or writing.
+fn store(path: &Path, entries: &Mutex<Entries>) -> Result<(), String> {
+    let dir = path.parent().unwrap(); // the ledger is always inside the archive
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let _lock = LockFile::acquire(&path.with_extension("lock"))?;
+
+    let mut merged = read_entries(path)?;
+    let json = {
+        let mut entries = entries.lock().unwrap();
+        merged.retain(|entry| !entries.changed.contains(&entry.job_id));
+        merged.extend(
+            entries
+                .entries
+                .iter()
+                .filter(|entry| entries.changed.contains(&entry.job_id))
+                .cloned(),
+        );
+        entries.entries = merged;
+        entries.changed.clear();
+        serde_json::to_string_pretty(&*entries).map_err(|e| e.to_string())?
+    };
+
+    // Readers never see a half written file
+    let written = path.with_extension("json.tmp");
+    fs::write(&written, json)
+        .and_then(|_| fs::rename(&written, path))
+        .map_err(|e| e.to_string())
+}
+
+fn release(entries: &mut Entries, letter: &LedgerEntry) {
+    entries
+        .uploading
+        .retain(|other| (&other.checksum, &other.files, &other.part) != (&letter.checksum, &letter.files, &letter.part));
+}
+
+// The entries of the ledger file, none if there is no file yet
+fn read_entries(path: &Path) -> Result<Vec<LedgerEntry>, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str::<Entries>(&text)
+            .map(|entries| entries.entries)
+            .map_err(|e| format!("Ledger {:?} is damaged: {}", path, e)),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+// Exclusive access to the ledger file, released when dropped
+struct LockFile {
+    path: PathBuf,
+}
+
+impl LockFile {
+    fn acquire(path: &Path) -> Result<LockFile, String> {
+        let start = Instant::now();
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(_) => return Ok(LockFile { path: path.into() }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let age = fs::metadata(path)
+                        .and_then(|md| md.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok());
+                    if age.is_some_and(|age| age > STALE_LOCK) {
+                        debug!("Removing stale lock {:?}", path);
+                        fs::remove_file(path).ok();
+                    } else if start.elapsed() > LOCK_TIMEOUT {
+                        return Err(format!("{:?} is locked by another lxp process", path));
+                    } else {
+                        std::thread::sleep(StdDuration::from_millis(50));
+                    }
+                }
+                Err(e) => return Err(format!("{:?}: {}", path, e)),
+            }
+        }
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+// Reason why a letter must not be sent again, if it is a duplicate
+fn duplicate(
+    entries: &[LedgerEntry],
+    pending: &[Job],
+    window: Duration,
+    letter: &LedgerEntry,
+) -> Option<String> {
+    // A deleted job was not sent, it may be uploaded again
+    let sent_before = entries
+        .iter()
+        .rev()
+        .filter(|entry| entry.same_letter(letter) && entry.status.as_deref() != Some(DELETED));
+    for entry in sent_before {
+        if let Some(job) = pending.iter().find(|job| job.id == entry.job_id) {
+            return Some(format!(
+                "the same content is waiting on the server as job {} ({})",
+                job.id,
+                job.status.as_str()
+            ));
+        }
+        let uploaded = entry.uploaded();
+        if uploaded > Utc::now() - window {
+            return Some(format!(
+                "the same content was sent as job {} on {}",
+                entry.job_id,
+                uploaded.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+            ));
+        }
+    }
+    // Letters sent from another computer are only known by name
+    pending
+        .iter()
+        .find(|job| job.address == letter.address && job.pages == letter.pages)
+        .map(|job| format!("job {} with the same name is waiting on the server", job.id))
+}
+
+// Jobs on hold or in the queue, duplicates of new letters may be among them
+async fn pending_jobs(api: &LxpApi) -> Result<Vec<Job>, LxpApiError> {
+    let mut jobs = api.get_jobs_hold().await?;
+    jobs.extend(api.get_jobs_queue(7).await?);
+    Ok(jobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(job_id: i32, address: &str) -> LedgerEntry {
+        let mut entry = LedgerEntry::upload(address, &[], address.as_bytes(), 1);
+        entry.job_id = job_id;
+        entry
+    }
+
+    #[tokio::test]
+    async fn concurrent_ledgers_are_merged() {
+        let dir = std::env::temp_dir().join(format!("lxp-ledger-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        let first = Ledger::load(&dir, DEFAULT_WINDOW_DAYS);
+        let second = Ledger::load(&dir, DEFAULT_WINDOW_DAYS);
+        first.record(entry(1, "a.pdf")).await;
+        second.record(entry(2, "b.pdf")).await;
+        second.mark_deleted(2);
+        first.update_job(1, 3, &ColorPrint::Color, &Mode::Duplex, &Ship::National);
+
+        let ids: Vec<i32> = read_entries(&dir.join("ledger.json"))
+            .unwrap()
+            .iter()
+            .map(|entry| entry.job_id)
+            .collect();
+        assert_eq!(ids, vec![2, 3]);
+        let ledger = Ledger::load(&dir, DEFAULT_WINDOW_DAYS);
+        assert_eq!(ledger.open_jobs(), vec![3]);
+        assert!(!dir.join("ledger.lock").exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn missing_jobs_seen_long_ago_are_unknown() {
+        let dir = std::env::temp_dir().join(format!("lxp-missing-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        let ledger = Ledger::load(&dir, DEFAULT_WINDOW_DAYS);
+        ledger.record(entry(1, "a.pdf")).await;
+        let mut old = entry(2, "b.pdf");
+        old.uploaded = (Utc::now() - Duration::days(40)).to_rfc3339();
+        ledger.record(old).await;
+
+        assert!(ledger.mark_missing(1));
+        assert!(!ledger.mark_missing(2));
+        // No entry, nothing is known about the job
+        assert!(!ledger.mark_missing(4));
+        assert!(ledger.open_jobs().is_empty());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn identical_parts_of_a_file_are_distinct_letters() {
+        let file = [PathBuf::from("run.pdf")];
+        let part = |pages| LedgerEntry::upload("run-001.pdf", &file, b"form", 1).split_from(&pages);
+        let window = Duration::days(DEFAULT_WINDOW_DAYS);
+        let sent = vec![part(1..=1)];
+
+        assert!(duplicate(&sent, &[], window, &part(2..=2)).is_none());
+        assert!(duplicate(&sent, &[], window, &part(1..=1)).is_some());
+        let other = LedgerEntry::upload("other.pdf", &[PathBuf::from("other.pdf")], b"form", 1);
+        assert!(duplicate(&sent, &[], window, &other).is_some());
+    }
+}
@@ -0,0 +1,103 @@
+/// Limits of LxpApi
+///
+/// All requests of a LxpApi instance and its clones share the limits: the
+/// number of requests in flight, the request rate and the timeouts. Commands
+/// uploading whole directories therefore neither hang on a dead connection
+/// nor overrun the web service.
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    /// Number of requests running at the same time
+    pub max_concurrent: usize,
+    /// Requests started per second, `None` for no limit; rates below one
+    /// request a day are raised to that
+    pub requests_per_second: Option<f64>,
+    /// Time allowed to establish a connection
+    pub connect_timeout: Duration,
+    /// Time allowed for a whole request including the answer
+    pub request_timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_concurrent: 5,
+            requests_per_second: None,
+            connect_timeout: Duration::from_secs(10),
+            // Uploads of large PDF documents take a while
+            request_timeout: Duration::from_secs(120),
+        }
+    }
+}
+
+// Longest time between two requests, lower rates are raised to this
+const MAX_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Enforces concurrency and rate of the requests
+#[derive(Debug, Clone)]
+pub(crate) struct Throttle {
+    permits: Arc<Semaphore>,
+    interval: Option<Duration>,
+    next_start: Arc<Mutex<Instant>>,
+}
+
+impl Throttle {
+    pub(crate) fn new(limits: &Limits) -> Throttle {
+        let interval = limits
+            .requests_per_second
+            .filter(|rps| *rps > 0.0)
+            .map(|rps| {
+                Duration::try_from_secs_f64(1.0 / rps)
+                    .unwrap_or(MAX_INTERVAL)
+                    .min(MAX_INTERVAL)
+            });
+        Throttle {
+            permits: Arc::new(Semaphore::new(limits.max_concurrent.max(1))),
+            interval,
+            next_start: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Wait until another request may be started, it may run as long as the
+    /// permit is held
+    pub(crate) async fn acquire(&self) -> SemaphorePermit<'_> {
+        let permit = self.permits.acquire().await;
+        if let Some(interval) = self.interval {
+            // Reserve the next free start time, then wait for it
+            let start = {
+                let mut next_start = self.next_start.lock().unwrap();
+                let start = (*next_start).max(Instant::now());
+                *next_start = start + interval;
+                start
+            };
+            tokio::time::delay_until(start).await;
+        }
+        permit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(requests_per_second: f64) -> Option<Duration> {
+        Throttle::new(&Limits {
+            requests_per_second: Some(requests_per_second),
+            ..Default::default()
+        })
+        .interval
+    }
+
+    #[test]
+    fn rate_gives_interval() {
+        assert_eq!(interval(4.0), Some(Duration::from_millis(250)));
+        assert_eq!(interval(0.0), None);
+        assert_eq!(interval(-1.0), None);
+        assert_eq!(interval(1e-20), Some(MAX_INTERVAL));
+        assert_eq!(interval(f64::MIN_POSITIVE), Some(MAX_INTERVAL));
+    }
+}
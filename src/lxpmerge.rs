@@ -0,0 +1,167 @@
+/// Merging of PDF documents into one letter
+///
+/// A cover letter and its attachments go into one envelope, so their
+/// documents are combined into a single document before uploading. The pages
+/// are taken in the order of the documents; bookmarks, forms and other
+/// document level information of the originals are dropped.
+use crate::lxppdf::{validate_pdf, PdfError};
+
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::fmt;
+
+// Page attributes that may be inherited from the page tree
+const INHERITABLE: &[&[u8]] = &[b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// Reason why documents could not be merged
+#[derive(Debug, Clone, PartialEq)]
+pub enum MergeError {
+    /// There is nothing to merge
+    NoDocuments,
+    /// The document at this position, counted from 0, is not valid
+    Pdf { index: usize, reason: PdfError },
+    /// The merged document could not be written
+    Merged(PdfError),
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeError::NoDocuments => write!(f, "no documents to merge"),
+            MergeError::Pdf { index, reason } => write!(f, "document {}: {}", index + 1, reason),
+            MergeError::Merged(reason) => write!(f, "merged document: {}", reason),
+        }
+    }
+}
+
+/// Combine PDF documents into one, the pages in the given order
+pub fn merge_pdfs(pdfs: &[&[u8]]) -> Result<Vec<u8>, MergeError> {
+    if pdfs.is_empty() {
+        return Err(MergeError::NoDocuments);
+    }
+
+    let mut merged = Document::with_version("1.4");
+    let mut pages = Vec::new();
+    let mut next_id = 1;
+    for (index, pdf) in pdfs.iter().enumerate() {
+        let error = |reason| MergeError::Pdf { index, reason };
+        validate_pdf(pdf).map_err(error)?;
+        let mut doc = Document::load_mem(pdf)
+            .map_err(|e| error(PdfError::Unparsable(e.to_string())))?;
+        if doc.version > merged.version {
+            merged.version = doc.version.clone();
+        }
+        // Object ids must not collide with those of the other documents
+        doc.renumber_objects_with(next_id);
+        next_id = doc.max_id + 1;
+
+        for page_id in doc.get_pages().into_values() {
+            let inherited = inherited_attributes(&doc, page_id);
+            if let Ok(Object::Dictionary(page)) = doc.get_object_mut(page_id) {
+                for (key, value) in inherited {
+                    page.set(key, value);
+                }
+            }
+            pages.push(page_id);
+        }
+        // The page trees and catalogs are replaced by a common one
+        for (id, object) in doc.objects {
+            let kind = object
+                .as_dict()
+                .and_then(|dict| dict.get(b"Type"))
+                .and_then(Object::as_name)
+                .unwrap_or_default();
+            if kind != b"Pages" && kind != b"Catalog" {
+                merged.objects.insert(id, object);
+            }
+        }
+    }
+
+    let pages_id = (next_id, 0);
+    for page_id in &pages {
+        if let Some(Object::Dictionary(page)) = merged.objects.get_mut(page_id) {
+            page.set("Parent", pages_id);
+        }
+    }
+    let mut page_tree = Dictionary::new();
+    page_tree.set("Type", "Pages");
+    page_tree.set("Count", pages.len() as i64);
+    page_tree.set(
+        "Kids",
+        pages.into_iter().map(Object::Reference).collect::<Vec<_>>(),
+    );
+    merged.objects.insert(pages_id, Object::Dictionary(page_tree));
+
+    let catalog_id = (next_id + 1, 0);
+    let mut catalog = Dictionary::new();
+    catalog.set("Type", "Catalog");
+    catalog.set("Pages", pages_id);
+    merged.objects.insert(catalog_id, Object::Dictionary(catalog));
+    merged.trailer.set("Root", catalog_id);
+    merged.max_id = next_id + 1;
+
+    // Whatever only the old catalogs referred to is left out
+    merged.prune_objects();
+    merged.renumber_objects();
+    let mut pdf = Vec::new();
+    merged
+        .save_to(&mut pdf)
+        .map_err(|e| MergeError::Merged(PdfError::Unparsable(e.to_string())))?;
+    Ok(pdf)
+}
+
+// Attributes a page inherits from its ancestors and does not set itself
+pub(crate) fn inherited_attributes(doc: &Document, page_id: ObjectId) -> Vec<(Vec<u8>, Object)> {
+    let mut attributes: Vec<(Vec<u8>, Object)> = Vec::new();
+    let page = match doc.get_dictionary(page_id) {
+        Ok(page) => page,
+        Err(_) => return attributes,
+    };
+    let mut parent = page.get(b"Parent").and_then(Object::as_reference).ok();
+    // Malformed documents may contain cycles
+    let mut depth = 0;
+    while let Some(id) = parent {
+        if depth == 32 {
+            break;
+        }
+        let node = match doc.get_dictionary(id) {
+            Ok(node) => node,
+            Err(_) => break,
+        };
+        for key in INHERITABLE {
+            let known = page.has(key) || attributes.iter().any(|(k, _)| k == key);
+            if let (false, Ok(value)) = (known, node.get(key)) {
+                attributes.push((key.to_vec(), value.clone()));
+            }
+        }
+        parent = node.get(b"Parent").and_then(Object::as_reference).ok();
+        depth += 1;
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes_are_inherited() {
+        let mut doc = Document::with_version("1.4");
+        let pages_id = doc.new_object_id();
+        let mut page = Dictionary::new();
+        page.set("Type", "Page");
+        page.set("Parent", pages_id);
+        page.set("Rotate", 90);
+        let page_id = doc.add_object(page);
+        let mut pages = Dictionary::new();
+        pages.set("Type", "Pages");
+        pages.set("Kids", vec![Object::Reference(page_id)]);
+        pages.set("Count", 1);
+        pages.set("MediaBox", vec![0.into(), 0.into(), 595.into(), 842.into()]);
+        pages.set("Rotate", 180);
+        doc.objects.insert(pages_id, Object::Dictionary(pages));
+
+        let attributes = inherited_attributes(&doc, page_id);
+        assert_eq!(attributes.len(), 1);
+        assert_eq!(attributes[0].0, b"MediaBox".to_vec());
+    }
+}
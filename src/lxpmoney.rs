@@ -0,0 +1,340 @@
+/// Money - exact amounts for costs, balances and invoices
+///
+/// LetterXpress sends amounts as decimal strings. They are stored as integer
+/// multiples of 1/10000 together with the currency, so sums are exact to the
+/// cent and beyond. Display rounds half away from zero to cents.
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub};
+use std::str::FromStr;
+
+/// Currency of amounts for which the web service does not name one
+pub const DEFAULT_CURRENCY: &str = "EUR";
+
+// Number of decimal places kept
+const SCALE: u32 = 4;
+const UNITS_PER_CENT: i64 = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Money {
+    units: i64,
+    currency: String,
+}
+
+impl Money {
+    pub fn zero(currency: &str) -> Money {
+        Money {
+            units: 0,
+            currency: currency.into(),
+        }
+    }
+
+    pub fn from_cents(cents: i64, currency: &str) -> Money {
+        Money {
+            units: cents * UNITS_PER_CENT,
+            currency: currency.into(),
+        }
+    }
+
+    /// Parse a decimal amount like "12.34" or "-0.1311"
+    pub fn parse(amount: &str, currency: &str) -> Result<Money, String> {
+        Ok(Money {
+            units: parse_units(amount)?,
+            currency: currency.into(),
+        })
+    }
+
+    /// The same amount in another currency, used when the currency is only
+    /// known after decoding the amount
+    pub fn with_currency(self, currency: &str) -> Money {
+        Money {
+            units: self.units,
+            currency: currency.into(),
+        }
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    /// Amount rounded to cents
+    pub fn cents(&self) -> i64 {
+        let cents = (self.units.abs() + UNITS_PER_CENT / 2) / UNITS_PER_CENT;
+        cents * self.units.signum()
+    }
+
+    /// The sum of both amounts, fails if their currencies differ
+    pub fn checked_add(&self, other: &Money) -> Result<Money, String> {
+        self.same_currency(other)?;
+        Ok(Money {
+            units: self.units + other.units,
+            currency: self.currency.clone(),
+        })
+    }
+
+    /// The difference of both amounts, fails if their currencies differ
+    pub fn checked_sub(&self, other: &Money) -> Result<Money, String> {
+        self.same_currency(other)?;
+        Ok(Money {
+            units: self.units - other.units,
+            currency: self.currency.clone(),
+        })
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), String> {
+        match self.currency == other.currency {
+            true => Ok(()),
+            false => Err(format!(
+                "amounts in {} and {} can't be combined",
+                self.currency, other.currency
+            )),
+        }
+    }
+
+    /// The amount multiplied by `n`, e.g. the price of several pages
+    pub fn times(&self, n: i64) -> Money {
+        Money {
+            units: self.units * n,
+            currency: self.currency.clone(),
+        }
+    }
+
+    /// `percent` % of the amount rounded half away from zero to cents, as
+    /// done for the VAT of a job
+    pub fn percent(&self, percent: u32) -> Money {
+        let divisor = 100 * UNITS_PER_CENT;
+        let cents = (self.units.abs() * percent as i64 + divisor / 2) / divisor;
+        Money::from_cents(cents * self.units.signum(), &self.currency)
+    }
+
+    // Amount with all significant decimal places, at least two
+    fn exact(&self) -> String {
+        let divisor = 10i64.pow(SCALE);
+        let mut frac = format!("{:04}", self.units.abs() % divisor);
+        while frac.len() > 2 && frac.ends_with('0') {
+            frac.pop();
+        }
+        let sign = if self.units < 0 { "-" } else { "" };
+        format!("{}{}.{}", sign, self.units.abs() / divisor, frac)
+    }
+}
+
+fn parse_units(amount: &str) -> Result<i64, String> {
+    let invalid = || format!("'{}' is not an amount", amount);
+    let trimmed = amount.trim();
+    let (negative, digits) = match trimmed.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let (int, frac) = match digits.find('.') {
+        Some(pos) => (&digits[..pos], &digits[pos + 1..]),
+        None => (digits, ""),
+    };
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if (int.is_empty() && frac.is_empty()) || !is_digits(int) || !is_digits(frac) {
+        return Err(invalid());
+    }
+    // Further decimal places are accepted as long as they are zero
+    let (frac, rest) = frac.split_at(frac.len().min(SCALE as usize));
+    if rest.chars().any(|c| c != '0') {
+        return Err(format!("'{}' has more than {} decimal places", amount, SCALE));
+    }
+
+    let int: i64 = if int.is_empty() {
+        0
+    } else {
+        int.parse().map_err(|_| invalid())?
+    };
+    let frac: i64 = format!("{:0<4}", frac).parse().map_err(|_| invalid())?;
+    let units = int
+        .checked_mul(10i64.pow(SCALE))
+        .and_then(|units| units.checked_add(frac))
+        .ok_or_else(invalid)?;
+    Ok(if negative { -units } else { units })
+}
+
+impl FromStr for Money {
+    type Err = String;
+
+    /// Parse an amount in the default currency
+    fn from_str(amount: &str) -> Result<Money, String> {
+        Money::parse(amount, DEFAULT_CURRENCY)
+    }
+}
+
+// Amounts of different currencies are not comparable
+impl PartialOrd for Money {
+    fn partial_cmp(&self, other: &Money) -> Option<Ordering> {
+        if self.currency == other.currency {
+            Some(self.units.cmp(&other.units))
+        } else {
+            None
+        }
+    }
+}
+
+/// # Panics
+///
+/// Adding amounts of different currencies panics, see `Money::checked_add`.
+impl Add for &Money {
+    type Output = Money;
+
+    fn add(self, other: &Money) -> Money {
+        self.checked_add(other).expect("Adding different currencies")
+    }
+}
+
+/// # Panics
+///
+/// Subtracting amounts of different currencies panics, see
+/// `Money::checked_sub`.
+impl Sub for &Money {
+    type Output = Money;
+
+    fn sub(self, other: &Money) -> Money {
+        self.checked_sub(other).expect("Subtracting different currencies")
+    }
+}
+
+impl AddAssign<&Money> for Money {
+    fn add_assign(&mut self, other: &Money) {
+        *self = &*self + other;
+    }
+}
+
+// user-facing output, rounded to cents, e.g. "12.35 €"
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cents = self.cents();
+        let sign = if cents < 0 { "-" } else { "" };
+        let symbol = match self.currency.as_str() {
+            "EUR" => "€",
+            currency => currency,
+        };
+        f.pad(&format!(
+            "{}{}.{:02} {}",
+            sign,
+            cents.abs() / 100,
+            cents.abs() % 100,
+            symbol
+        ))
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.exact())
+    }
+}
+
+// The web service sends amounts as string, accept numbers as well
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+        struct MoneyVisitor;
+
+        impl<'de> Visitor<'de> for MoneyVisitor {
+            type Value = Money;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a decimal amount")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Money, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Money, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Money, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Money, E> {
+                self.visit_str(&v.to_string())
+            }
+        }
+
+        deserializer.deserialize_any(MoneyVisitor)
+    }
+}
+
+impl Default for Money {
+    fn default() -> Money {
+        Money::zero(DEFAULT_CURRENCY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Money::from_str("12.34").unwrap().units, 123_400);
+        assert_eq!(Money::from_str("-0.1311").unwrap().units, -1311);
+        assert_eq!(Money::from_str("7").unwrap().units, 70_000);
+        assert_eq!(Money::from_str(".5").unwrap().units, 5000);
+        assert_eq!(Money::from_str("0.780000").unwrap().units, 7800);
+        assert!(Money::from_str("0.00001").is_err());
+        assert!(Money::from_str("1,50").is_err());
+        assert!(Money::from_str("").is_err());
+        assert!(Money::from_str("-").is_err());
+    }
+
+    #[test]
+    fn sums_are_exact() {
+        let mut sum = Money::zero(DEFAULT_CURRENCY);
+        for _ in 0..10 {
+            sum += &Money::from_str("0.1").unwrap();
+        }
+        assert_eq!(sum, Money::from_cents(100, DEFAULT_CURRENCY));
+    }
+
+    #[test]
+    fn times_and_percent() {
+        let page = Money::from_str("0.05").unwrap();
+        assert_eq!(page.times(3), Money::from_str("0.15").unwrap());
+        let cost = Money::from_str("0.93").unwrap();
+        assert_eq!(cost.percent(19), Money::from_cents(18, DEFAULT_CURRENCY));
+        assert_eq!(cost.times(-1).percent(19), Money::from_cents(-18, DEFAULT_CURRENCY));
+        assert_eq!(&cost - &page, Money::from_str("0.88").unwrap());
+    }
+
+    #[test]
+    fn display_rounds_to_cents() {
+        assert_eq!(Money::from_str("0.125").unwrap().to_string(), "0.13 €");
+        assert_eq!(Money::from_str("-0.125").unwrap().to_string(), "-0.13 €");
+        assert_eq!(format!("{:>8}", Money::from_str("27.12").unwrap()), " 27.12 €");
+        assert_eq!(Money::parse("3", "CHF").unwrap().to_string(), "3.00 CHF");
+    }
+
+    #[test]
+    fn compare_only_same_currency() {
+        let eur = Money::from_str("1").unwrap();
+        let chf = Money::parse("2", "CHF").unwrap();
+        assert!(eur < Money::from_str("1.01").unwrap());
+        assert_eq!(eur.partial_cmp(&chf), None);
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        let eur = Money::from_str("1").unwrap();
+        let chf = Money::parse("2", "CHF").unwrap();
+        assert_eq!(eur.checked_add(&eur), Ok(Money::from_cents(200, DEFAULT_CURRENCY)));
+        assert_eq!(eur.checked_sub(&eur), Ok(Money::zero(DEFAULT_CURRENCY)));
+        assert!(eur.checked_add(&chf).is_err());
+        assert!(chf.checked_sub(&eur).is_err());
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let money: Money = serde_json::from_str("\"0.1311\"").unwrap();
+        assert_eq!(serde_json::to_string(&money).unwrap(), "\"0.1311\"");
+        let money: Money = serde_json::from_str("0.87").unwrap();
+        assert_eq!(serde_json::to_string(&money).unwrap(), "\"0.87\"");
+    }
+}
@@ -0,0 +1,81 @@
+/// Local preflight of PDF documents
+///
+/// Before a letter is uploaded, the document is checked for the things the
+/// print service would reject anyway: it must start with a PDF header, end
+/// with an end-of-file marker, be parsable, not be encrypted and contain at
+/// least one page. This avoids network traffic and fees for broken files.
+use std::fmt;
+
+// Readers accept header and end marker a bit away from the file boundaries
+const MARKER_SEARCH_LEN: usize = 1024;
+
+/// Facts about a valid PDF document
+#[derive(Debug, Clone, PartialEq)]
+pub struct PdfInfo {
+    pub version: String,
+    pub pages: u32,
+}
+
+/// Reason why a document is no valid PDF document
+#[derive(Debug, Clone, PartialEq)]
+pub enum PdfError {
+    /// The document does not start with "%PDF-", it is some other file type
+    NoHeader,
+    /// The "%%EOF" marker is missing, the file is probably truncated
+    NoEndMarker,
+    /// The document structure could not be parsed
+    Unparsable(String),
+    /// The document is encrypted and can't be printed
+    Encrypted,
+    /// The document has no pages
+    NoPages,
+}
+
+impl fmt::Display for PdfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PdfError::NoHeader => write!(f, "no PDF header, this is not a PDF document"),
+            PdfError::NoEndMarker => write!(f, "no end of file marker, the file is truncated"),
+            PdfError::Unparsable(e) => write!(f, "document structure is damaged: {}", e),
+            PdfError::Encrypted => write!(f, "document is encrypted"),
+            PdfError::NoPages => write!(f, "document has no pages"),
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Check a PDF document held in memory
+pub fn validate_pdf(pdf: &[u8]) -> Result<PdfInfo, PdfError> {
+    let head = &pdf[..pdf.len().min(MARKER_SEARCH_LEN)];
+    if find(head, b"%PDF-").is_none() {
+        return Err(PdfError::NoHeader);
+    }
+    let tail = &pdf[pdf.len().saturating_sub(MARKER_SEARCH_LEN)..];
+    if find(tail, b"%%EOF").is_none() {
+        return Err(PdfError::NoEndMarker);
+    }
+
+    let doc = match lopdf::Document::load_mem(pdf) {
+        Ok(doc) => doc,
+        // The parser may stumble over encrypted object streams
+        Err(_) if find(pdf, b"/Encrypt").is_some() => return Err(PdfError::Encrypted),
+        Err(e) => return Err(PdfError::Unparsable(e.to_string())),
+    };
+    if doc.trailer.get(b"Encrypt").is_ok() {
+        return Err(PdfError::Encrypted);
+    }
+
+    let pages = doc.get_pages().len() as u32;
+    if pages == 0 {
+        return Err(PdfError::NoPages);
+    }
+    Ok(PdfInfo {
+        version: doc.version,
+        pages,
+    })
+}
@@ -0,0 +1,100 @@
+/// Redaction of requests and answers for logging
+///
+/// Requests carry the api key and complete PDF documents in base64, answers
+/// to invoice requests the invoice PDF. Logged verbatim, the log file would
+/// leak the credentials and grow by megabytes per letter. So the api key is
+/// masked, documents are replaced by their size and checksum, and any other
+/// long string is shortened.
+use serde::Serialize;
+use serde_json::Value;
+
+// Strings longer than this are shortened
+const MAX_STRING_LEN: usize = 80;
+// Answers that are no JSON are shortened to this length
+const MAX_TEXT_LEN: usize = 1000;
+
+/// Serialize a request body for the log
+pub(crate) fn for_log<T: Serialize>(body: &T) -> String {
+    match serde_json::to_value(body) {
+        Ok(mut value) => {
+            redact(&mut value);
+            value.to_string()
+        }
+        Err(e) => format!("<not serializable: {}>", e),
+    }
+}
+
+/// Prepare the text of an answer for the log
+pub(crate) fn text_for_log(text: &str) -> String {
+    match serde_json::from_str::<Value>(text) {
+        Ok(mut value) => {
+            redact(&mut value);
+            value.to_string()
+        }
+        Err(_) => truncate(text, MAX_TEXT_LEN),
+    }
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match (key.as_str(), &value) {
+                    ("apikey", _) => *value = Value::from("***"),
+                    ("base64_file", Value::String(file)) => {
+                        *value = Value::from(format!(
+                            "<{} bytes base64, md5 {:x}>",
+                            file.len(),
+                            md5::compute(file)
+                        ))
+                    }
+                    _ => redact(value),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        Value::String(s) => *s = truncate(s, MAX_STRING_LEN),
+        _ => (),
+    }
+}
+
+// Keep the beginning of a long text, respecting char boundaries
+fn truncate(text: &str, max_len: usize) -> String {
+    match text.char_indices().nth(max_len) {
+        Some((idx, _)) => format!("{}...<{} bytes>", &text[..idx], text.len()),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn secrets_and_documents_are_redacted() {
+        let body = json!({
+            "auth": { "username": "user", "apikey": "0123456789abcdef" },
+            "letter": { "base64_file": "JVBERi0xLjQK", "address": "letter.pdf" },
+        });
+        let logged = for_log(&body);
+        assert!(!logged.contains("0123456789abcdef"));
+        assert!(!logged.contains("JVBERi0xLjQK"));
+        assert!(logged.contains("\"apikey\":\"***\""));
+        assert!(logged.contains(&format!("<12 bytes base64, md5 {:x}>", md5::compute("JVBERi0xLjQK"))));
+        assert!(logged.contains("letter.pdf"));
+    }
+
+    #[test]
+    fn long_answers_are_truncated() {
+        let pdf_data = "A".repeat(100_000);
+        let answer = json!({ "invoice": { "iid": "30711", "pdf_data": pdf_data } }).to_string();
+        let logged = text_for_log(&answer);
+        assert!(logged.len() < 200);
+        assert!(logged.contains("30711"));
+        assert!(logged.contains("...<100000 bytes>"));
+
+        let html = "<html>".repeat(1000);
+        assert_eq!(text_for_log(&html).len(), MAX_TEXT_LEN + "...<6000 bytes>".len());
+    }
+}
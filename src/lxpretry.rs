@@ -0,0 +1,95 @@
+/// Retry policy of LxpApi
+///
+/// Transient failures (connection problems, timeouts, overloaded server) are
+/// retried with an exponential backoff. The delay doubles with every attempt,
+/// is limited by `max_delay` and varied randomly by `jitter` so that several
+/// clients do not hammer the web service in lockstep.
+use crate::lxperror::LxpApiError;
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Number of attempts including the first one, 1 disables retries
+    pub max_attempts: u32,
+    /// Delay before the first retry
+    pub base_delay: Duration,
+    /// Upper limit of the delay between two attempts
+    pub max_delay: Duration,
+    /// Random variation of the delay, 0.0 (none) to 1.0 (+/- 100 %)
+    pub jitter: f64,
+    /// Retry if the connection failed or no answer was received
+    pub retry_transport: bool,
+    /// HTTP and web service status codes that are retried
+    pub retry_statuses: Vec<i32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.25,
+            retry_transport: true,
+            retry_statuses: vec![429, 500, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Is the error worth another try?
+    pub fn is_transient(&self, e: &LxpApiError) -> bool {
+        match e {
+            LxpApiError::Transport(_) => self.retry_transport,
+            _ => match e.status() {
+                Some(status) => self.retry_statuses.contains(&status),
+                None => false,
+            },
+        }
+    }
+
+    /// Is it certain that the failed request was not processed by the web
+    /// service? Only then a non-idempotent request may be repeated blindly.
+    pub fn is_unprocessed(&self, e: &LxpApiError) -> bool {
+        match e {
+            LxpApiError::Transport(e) => self.retry_transport && e.is_connect(),
+            _ => match e.status() {
+                Some(status) => {
+                    (status == 429 || status == 503) && self.retry_statuses.contains(&status)
+                }
+                None => false,
+            },
+        }
+    }
+
+    /// Shall an idempotent request be repeated after `attempt` failed?
+    pub fn retry(&self, attempt: u32, e: &LxpApiError) -> bool {
+        attempt < self.max_attempts && self.is_transient(e)
+    }
+
+    /// Delay after the failed attempt `attempt` (starting with 1)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self
+            .base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let variation = 1.0 - jitter + 2.0 * jitter * random_fraction();
+        delay.mul_f64(variation)
+    }
+}
+
+// Random number in 0.0..1.0, good enough to spread retries
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u32(now.subsec_nanos());
+    }
+    (hasher.finish() % 10_000) as f64 / 10_000.0
+}
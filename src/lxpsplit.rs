@@ -0,0 +1,309 @@
+/// Splitting of PDF documents into letters
+///
+/// Some programs print a whole run of letters into one PDF document. To send
+/// them as separate letters, the document is cut apart: into parts with a
+/// fixed number of pages, along a list of page ranges, or at separator pages.
+/// A separator page is either blank or contains a marker text; it only
+/// delimits the letters and is not part of any of them.
+use crate::lxpmerge::inherited_attributes;
+use crate::lxppdf::{validate_pdf, PdfError};
+
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+// Operators that put something visible on a page
+const PAINT_OPERATORS: &[&str] = &[
+    "Tj", "TJ", "'", "\"", "Do", "BI", "sh", "f", "F", "f*", "B", "B*", "b", "b*", "S", "s",
+];
+
+/// Where a document is cut into letters
+#[derive(Debug, Clone, PartialEq)]
+pub enum SplitRule {
+    /// Every letter has this number of pages, the last one may have less
+    Pages(u32),
+    /// Each range of pages, counted from 1, is a letter
+    Ranges(Vec<RangeInclusive<u32>>),
+    /// Pages containing this text separate the letters
+    Separator(String),
+    /// Blank pages separate the letters
+    BlankPage,
+}
+
+/// A letter cut out of a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct PdfPart {
+    /// First and last page in the original document, counted from 1
+    pub pages: RangeInclusive<u32>,
+    pub pdf: Vec<u8>,
+}
+
+/// Reason why a document could not be split
+#[derive(Debug, Clone, PartialEq)]
+pub enum SplitError {
+    /// The document itself is not valid
+    Pdf(PdfError),
+    /// A page range does not fit the document
+    Range { range: RangeInclusive<u32>, pages: u32 },
+    /// A list of page ranges could not be read
+    RangeList { line: usize, text: String },
+    /// The rule leaves no letter, e.g. all pages are separators
+    NoLetters,
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SplitError::Pdf(e) => write!(f, "{}", e),
+            SplitError::Range { range, pages } => write!(
+                f,
+                "pages {}-{} are not within the {} pages of the document",
+                range.start(),
+                range.end(),
+                pages
+            ),
+            SplitError::RangeList { line, text } => {
+                write!(f, "line {}: '{}' is no page range like 1-3", line, text)
+            }
+            SplitError::NoLetters => write!(f, "no letters found in the document"),
+        }
+    }
+}
+
+/// Read page ranges, one letter per line like "1-3" or "4". Several ranges
+/// in one line are separated by commas, "#" starts a comment.
+pub fn parse_page_ranges(text: &str) -> Result<Vec<RangeInclusive<u32>>, SplitError> {
+    let mut ranges = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        for item in line.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let error = || SplitError::RangeList {
+                line: idx + 1,
+                text: item.to_string(),
+            };
+            let (first, last) = match item.find('-') {
+                Some(pos) => (&item[..pos], &item[pos + 1..]),
+                None => (item, item),
+            };
+            let first: u32 = first.trim().parse().map_err(|_| error())?;
+            let last: u32 = last.trim().parse().map_err(|_| error())?;
+            if first == 0 || last < first {
+                return Err(error());
+            }
+            ranges.push(first..=last);
+        }
+    }
+    Ok(ranges)
+}
+
+/// Cut a PDF document into letters
+pub fn split_pdf(pdf: &[u8], rule: &SplitRule) -> Result<Vec<PdfPart>, SplitError> {
+    let pages = validate_pdf(pdf).map_err(SplitError::Pdf)?.pages;
+    let doc = Document::load_mem(pdf).map_err(unparsable)?;
+    let page_ids = doc.get_pages();
+
+    let ranges = match rule {
+        SplitRule::Pages(count) => {
+            let count = (*count).max(1);
+            (0..pages.div_ceil(count))
+                .map(|idx| idx * count + 1..=((idx + 1) * count).min(pages))
+                .collect()
+        }
+        SplitRule::Ranges(ranges) => {
+            if let Some(range) = ranges.iter().find(|range| *range.end() > pages) {
+                return Err(SplitError::Range {
+                    range: range.clone(),
+                    pages,
+                });
+            }
+            ranges.clone()
+        }
+        SplitRule::Separator(marker) => between_separators(pages, |page| {
+            page_text(&doc, page_ids[&page]).contains(marker.as_str())
+        }),
+        SplitRule::BlankPage => between_separators(pages, |page| is_blank(&doc, page_ids[&page])),
+    };
+    if ranges.is_empty() {
+        return Err(SplitError::NoLetters);
+    }
+
+    ranges
+        .into_iter()
+        .map(|range| {
+            let page_ids: Vec<ObjectId> = range.clone().map(|page| page_ids[&page]).collect();
+            let mut part = extract_pages(&doc, &page_ids);
+            let mut pdf = Vec::new();
+            part.save_to(&mut pdf)
+                .map_err(|e| SplitError::Pdf(PdfError::Unparsable(e.to_string())))?;
+            Ok(PdfPart { pages: range, pdf })
+        })
+        .collect()
+}
+
+fn unparsable(e: lopdf::Error) -> SplitError {
+    SplitError::Pdf(PdfError::Unparsable(e.to_string()))
+}
+
+// The runs of pages between separator pages
+fn between_separators<F: Fn(u32) -> bool>(pages: u32, is_separator: F) -> Vec<RangeInclusive<u32>> {
+    let mut ranges = Vec::new();
+    let mut first = None;
+    for page in 1..=pages {
+        if is_separator(page) {
+            if let Some(first) = first.take() {
+                ranges.push(first..=page - 1);
+            }
+        } else if first.is_none() {
+            first = Some(page);
+        }
+    }
+    if let Some(first) = first {
+        ranges.push(first..=pages);
+    }
+    ranges
+}
+
+fn page_operations(doc: &Document, page_id: ObjectId) -> Vec<lopdf::content::Operation> {
+    doc.get_page_content(page_id)
+        .ok()
+        .and_then(|content| Content::decode(&content).ok())
+        .map(|content| content.operations)
+        .unwrap_or_default()
+}
+
+// Text shown on a page. Document::extract_text logs every font encoding, and
+// markers are plain ASCII anyway, so the strings are taken byte by byte.
+fn page_text(doc: &Document, page_id: ObjectId) -> String {
+    fn collect(text: &mut String, operands: &[Object]) {
+        for operand in operands {
+            match operand {
+                Object::String(bytes, _) => text.extend(bytes.iter().map(|b| *b as char)),
+                Object::Array(items) => collect(text, items),
+                _ => (),
+            }
+        }
+    }
+    let mut text = String::new();
+    for operation in page_operations(doc, page_id) {
+        match operation.operator.as_str() {
+            "Tj" | "TJ" | "'" | "\"" => collect(&mut text, &operation.operands),
+            "ET" => text.push('\n'),
+            _ => (),
+        }
+    }
+    text
+}
+
+fn is_blank(doc: &Document, page_id: ObjectId) -> bool {
+    !page_operations(doc, page_id)
+        .iter()
+        .any(|operation| PAINT_OPERATORS.contains(&operation.operator.as_str()))
+}
+
+// A new document of the given pages and the objects they use. Only these
+// objects are copied, so cutting a long document costs as much as its size.
+fn extract_pages(doc: &Document, page_ids: &[ObjectId]) -> Document {
+    let mut part = Document::with_version(&doc.version);
+    let pages_id = (doc.max_id + 1, 0);
+    let catalog_id = (doc.max_id + 2, 0);
+    let other_pages: BTreeSet<ObjectId> = doc
+        .get_pages()
+        .into_values()
+        .filter(|id| !page_ids.contains(id))
+        .collect();
+
+    let mut todo: Vec<ObjectId> = Vec::new();
+    for page_id in page_ids {
+        let mut page = match doc.get_dictionary(*page_id) {
+            Ok(page) => page.clone(),
+            Err(_) => continue,
+        };
+        for (key, value) in inherited_attributes(doc, *page_id) {
+            page.set(key, value);
+        }
+        page.set("Parent", pages_id);
+        dict_references(&page, &mut todo);
+        part.objects.insert(*page_id, Object::Dictionary(page));
+    }
+    // Whatever the pages use, but neither the page tree nor other pages
+    while let Some(id) = todo.pop() {
+        if part.objects.contains_key(&id) || other_pages.contains(&id) || id == pages_id {
+            continue;
+        }
+        if let Ok(object) = doc.get_object(id) {
+            let kind = object
+                .as_dict()
+                .and_then(|dict| dict.get(b"Type"))
+                .and_then(Object::as_name)
+                .unwrap_or_default();
+            if kind != b"Pages" && kind != b"Catalog" {
+                references(object, &mut todo);
+                part.objects.insert(id, object.clone());
+            }
+        }
+    }
+
+    let mut page_tree = Dictionary::new();
+    page_tree.set("Type", "Pages");
+    page_tree.set("Count", page_ids.len() as i64);
+    page_tree.set(
+        "Kids",
+        page_ids.iter().map(|id| Object::Reference(*id)).collect::<Vec<_>>(),
+    );
+    part.objects.insert(pages_id, Object::Dictionary(page_tree));
+    let mut catalog = Dictionary::new();
+    catalog.set("Type", "Catalog");
+    catalog.set("Pages", pages_id);
+    part.objects.insert(catalog_id, Object::Dictionary(catalog));
+    part.trailer.set("Root", catalog_id);
+    part.max_id = catalog_id.0;
+    part.renumber_objects();
+    part
+}
+
+// Objects referred to by an object, the parents in the page tree left out
+fn references(object: &Object, ids: &mut Vec<ObjectId>) {
+    match object {
+        Object::Reference(id) => ids.push(*id),
+        Object::Array(items) => items.iter().for_each(|item| references(item, ids)),
+        Object::Dictionary(dict) => dict_references(dict, ids),
+        Object::Stream(stream) => dict_references(&stream.dict, ids),
+        _ => (),
+    }
+}
+
+fn dict_references(dict: &Dictionary, ids: &mut Vec<ObjectId>) {
+    dict.iter()
+        .filter(|(key, _)| key.as_slice() != b"Parent")
+        .for_each(|(_, value)| references(value, ids));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_ranges() {
+        let ranges = parse_page_ranges("1-3\n4 # one page\n\n5-6, 7-8\n").unwrap();
+        assert_eq!(ranges, vec![1..=3, 4..=4, 5..=6, 7..=8]);
+        assert_eq!(
+            parse_page_ranges("1-3\n5-4\n"),
+            Err(SplitError::RangeList {
+                line: 2,
+                text: "5-4".into()
+            })
+        );
+        assert!(parse_page_ranges("0-2").is_err());
+        assert!(parse_page_ranges("a").is_err());
+    }
+
+    #[test]
+    fn runs_between_separators() {
+        let separators = [1, 4, 5, 8];
+        let ranges = between_separators(9, |page| separators.contains(&page));
+        assert_eq!(ranges, vec![2..=3, 6..=7, 9..=9]);
+        assert!(between_separators(2, |_| true).is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+/// Tariff - local cost estimate of letters
+///
+/// The price of a letter consists of a base price for envelope, postage and
+/// the first sheet, a price for every further sheet and a print price per
+/// page. Duplex letters print two pages on one sheet. The web service does
+/// not publish its prices, so the table is configurable; the defaults are
+/// only an example and should be adjusted to the current price list.
+use crate::lxpmoney::Money;
+use crate::lxptypes::{ColorPrint, Mode, Ship};
+
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tariff {
+    /// Letter within Germany including envelope and the first sheet
+    pub letter_national: Money,
+    /// Letter abroad including envelope and the first sheet
+    pub letter_international: Money,
+    /// Each further sheet
+    pub sheet: Money,
+    /// Black and white print per page
+    pub page_black_and_white: Money,
+    /// Color print per page
+    pub page_color: Money,
+    /// VAT added to the net price
+    pub vat_percent: u32,
+}
+
+impl Default for Tariff {
+    fn default() -> Tariff {
+        let price = |amount| Money::from_str(amount).unwrap();
+        Tariff {
+            letter_national: price("0.68"),
+            letter_international: price("1.38"),
+            sheet: price("0.02"),
+            page_black_and_white: price("0.05"),
+            page_color: price("0.10"),
+            vat_percent: 19,
+        }
+    }
+}
+
+/// Estimated cost of a single letter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    pub pages: u32,
+    pub sheets: u32,
+    pub cost: Money,
+    pub cost_vat: Money,
+}
+
+impl Estimate {
+    /// Cost including VAT, this is charged to the credit
+    pub fn total(&self) -> Money {
+        &self.cost + &self.cost_vat
+    }
+}
+
+impl Tariff {
+    /// Estimate the cost of a letter with `pages` pages
+    pub fn estimate(&self, pages: u32, color: &ColorPrint, mode: &Mode, ship: &Ship) -> Estimate {
+        let sheets = match mode {
+            Mode::Simplex => pages,
+            Mode::Duplex => pages.div_ceil(2),
+        };
+        let letter = match ship {
+            Ship::National => &self.letter_national,
+            Ship::International => &self.letter_international,
+        };
+        let page = match color {
+            ColorPrint::BlackAndWhite => &self.page_black_and_white,
+            ColorPrint::Color => &self.page_color,
+        };
+        let cost = &(letter + &self.sheet.times(sheets.saturating_sub(1) as i64))
+            + &page.times(pages as i64);
+        Estimate {
+            pages,
+            sheets,
+            cost_vat: cost.percent(self.vat_percent),
+            cost,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simplex_and_duplex() {
+        let tariff = Tariff::default();
+        let simplex = tariff.estimate(3, &ColorPrint::Color, &Mode::Simplex, &Ship::National);
+        assert_eq!(simplex.sheets, 3);
+        assert_eq!(simplex.cost, Money::from_str("1.02").unwrap());
+        assert_eq!(simplex.cost_vat, Money::from_str("0.19").unwrap());
+
+        let duplex = tariff.estimate(3, &ColorPrint::Color, &Mode::Duplex, &Ship::National);
+        assert_eq!(duplex.sheets, 2);
+        assert_eq!(duplex.cost, Money::from_str("1.00").unwrap());
+    }
+
+    #[test]
+    fn black_and_white_international() {
+        let tariff = Tariff::default();
+        let estimate = tariff.estimate(
+            1,
+            &ColorPrint::BlackAndWhite,
+            &Mode::Simplex,
+            &Ship::International,
+        );
+        assert_eq!(estimate.cost, Money::from_str("1.43").unwrap());
+        assert_eq!(estimate.total(), Money::from_str("1.70").unwrap());
+    }
+}
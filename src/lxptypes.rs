@@ -1,28 +1,278 @@
-use serde::{Deserialize, Serialize};
+pub use crate::lxpmoney::Money;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
 // Some Enums for lxpapi
-#[derive(Debug, Copy, Clone)]
-#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ColorPrint {
     Color,
     BlackAndWhite,
 }
 
-#[derive(Debug, Copy, Clone)]
-#[allow(dead_code)]
+impl ColorPrint {
+    /// Code used by the web service
+    pub fn code(self) -> i32 {
+        match self {
+            ColorPrint::Color => 4,
+            ColorPrint::BlackAndWhite => 1,
+        }
+    }
+
+    fn from_code(code: &str) -> Result<ColorPrint, String> {
+        match code {
+            "4" => Ok(ColorPrint::Color),
+            "1" => Ok(ColorPrint::BlackAndWhite),
+            _ => Err(format!("unknown color '{}'", code)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Mode {
     Simplex,
     Duplex,
 }
 
-#[derive(Debug, Copy, Clone)]
-#[allow(dead_code)]
+impl Mode {
+    /// Name used by the web service
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mode::Simplex => "simplex",
+            Mode::Duplex => "duplex",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Mode, String> {
+        match name {
+            "simplex" => Ok(Mode::Simplex),
+            "duplex" => Ok(Mode::Duplex),
+            _ => Err(format!("unknown mode '{}'", name)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Ship {
     National,
     International,
 }
 
+impl Ship {
+    /// Name used by the web service
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Ship::National => "national",
+            Ship::International => "international",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Ship, String> {
+        match name {
+            "national" => Ok(Ship::National),
+            "international" => Ok(Ship::International),
+            _ => Err(format!("unknown shipping '{}'", name)),
+        }
+    }
+}
+
+/// State of a job on the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobStatus {
+    /// Waiting for sufficient credit
+    Hold,
+    /// Accepted, will be printed and sent soon
+    Queue,
+    /// Handed over to the postal service
+    Sent,
+    /// A state this version does not know
+    Other(String),
+}
+
+impl JobStatus {
+    /// Name used by the web service
+    pub fn as_str(&self) -> &str {
+        match self {
+            JobStatus::Hold => "hold",
+            JobStatus::Queue => "queue",
+            JobStatus::Sent => "sent",
+            JobStatus::Other(name) => name,
+        }
+    }
+
+    fn from_name(name: &str) -> JobStatus {
+        match name {
+            "hold" => JobStatus::Hold,
+            "queue" => JobStatus::Queue,
+            "sent" => JobStatus::Sent,
+            _ => JobStatus::Other(name.to_string()),
+        }
+    }
+}
+
+// Typed results of the LxpApi methods
+
+/// Credit balance of the account
+#[derive(Debug, Clone, PartialEq)]
+pub struct Balance {
+    pub value: Money,
+}
+
+/// The result of checking the access to the web service
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessCheck {
+    /// Account as reported by the web service
+    pub account_id: Option<String>,
+    pub user: Option<String>,
+    pub account_status: Option<String>,
+    pub balance: Money,
+    /// Time from sending the request to receiving the answer
+    pub latency: std::time::Duration,
+}
+
+/// A print job on the server
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    pub id: i32,
+    pub address: String,
+    pub parent: Option<i32>,
+    pub status: JobStatus,
+    pub mode: Mode,
+    pub color: ColorPrint,
+    pub cover: String,
+    pub shipping: Ship,
+    pub pages: u32,
+    pub cost: Money,
+    pub cost_vat: Money,
+    pub date: DateTime<Utc>,
+    pub dispatchdate: Option<NaiveDate>,
+    pub sentdate: Option<DateTime<Utc>>,
+}
+
+/// An invoice as listed by the server
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvoiceSummary {
+    pub id: i32,
+    pub date: NaiveDate,
+    pub sum: Money,
+    pub vat: Money,
+}
+
+/// An invoice together with its PDF document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invoice {
+    pub meta: InvoiceSummary,
+    pub pdf: Vec<u8>,
+}
+
+/// The result of uploading a letter
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobCreated {
+    pub id: i32,
+    /// Lines found in the address window, empty if it was not checked
+    pub recipient: Vec<String>,
+}
+
+/// The result of changing the print options of a job
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobUpdated {
+    /// Id of the job, a new one if the job was uploaded again
+    pub id: i32,
+    /// The job was deleted and uploaded again
+    pub resubmitted: bool,
+}
+
+fn parse_id(what: &str, id: &str) -> Result<i32, String> {
+    id.parse::<i32>()
+        .map_err(|_| format!("{} '{}' is not a number", what, id))
+}
+
+
+impl TryFrom<&SubBalance> for Balance {
+    type Error = String;
+
+    fn try_from(balance: &SubBalance) -> Result<Balance, String> {
+        Ok(Balance {
+            value: balance.value.clone().with_currency(&balance.currency),
+        })
+    }
+}
+
+// A job with its amounts in the currency of the account
+impl TryFrom<(&SubJobArgs, &str)> for Job {
+    type Error = String;
+
+    fn try_from((job, currency): (&SubJobArgs, &str)) -> Result<Job, String> {
+        Ok(Job {
+            id: parse_id("job id", &job.jid)?,
+            address: job.address.clone(),
+            parent: match &job.parent {
+                Some(parent) => Some(parse_id("parent job id", parent)?),
+                None => None,
+            },
+            status: JobStatus::from_name(&job.status),
+            mode: Mode::from_name(&job.mode)?,
+            color: ColorPrint::from_code(&job.color)?,
+            cover: job.cover.clone(),
+            shipping: Ship::from_name(&job.shipping)?,
+            pages: job
+                .pages
+                .parse::<u32>()
+                .map_err(|_| format!("page count '{}' is not a number", job.pages))?,
+            cost: job.cost.clone().with_currency(currency),
+            cost_vat: job.cost_vat.clone().with_currency(currency),
+            date: job.date,
+            dispatchdate: job.dispatchdate,
+            sentdate: job.sentdate,
+        })
+    }
+}
+
+// An invoice with its amounts in the currency of the account
+impl TryFrom<(&SubInvoice, &str)> for InvoiceSummary {
+    type Error = String;
+
+    fn try_from((invoice, currency): (&SubInvoice, &str)) -> Result<InvoiceSummary, String> {
+        Ok(InvoiceSummary {
+            id: parse_id("invoice id", &invoice.iid)?,
+            date: invoice.invoicedate,
+            sum: invoice.sum.clone().with_currency(currency),
+            vat: invoice.vat.clone().with_currency(currency),
+        })
+    }
+}
+
+impl TryFrom<(&SubInvoice, &str)> for Invoice {
+    type Error = String;
+
+    fn try_from((invoice, currency): (&SubInvoice, &str)) -> Result<Invoice, String> {
+        let pdf_data = invoice
+            .pdf_data
+            .as_ref()
+            .ok_or_else(|| String::from("invoice without PDF data"))?;
+        Ok(Invoice {
+            meta: InvoiceSummary::try_from((invoice, currency))?,
+            pdf: base64::decode(pdf_data)
+                .map_err(|e| format!("invoice PDF data is no valid base64: {}", e))?,
+        })
+    }
+}
+
+// The web service sends ids as string or as number
+fn de_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Id {
+        Text(String),
+        Number(i64),
+    }
+    Ok(match Id::deserialize(deserializer)? {
+        Id::Text(id) => id,
+        Id::Number(id) => id.to_string(),
+    })
+}
+
 // Substructures used in request and response structs
 #[allow(dead_code)]
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -35,23 +285,24 @@ pub struct SubAuth {
 #[allow(dead_code)]
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct SubBalance {
-    pub value: String,
+    pub value: Money,
     pub currency: String,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SubInvoice {
     pub iid: String,
-    pub invoicedate: String,
+    #[serde(with = "crate::lxpdate::date")]
+    pub invoicedate: NaiveDate,
     pub pdf_data: Option<String>,
     pub bin_pdf_data: Option<Vec<u8>>,
-    pub sum: String,
-    pub vat: String,
+    pub sum: Money,
+    pub vat: Money,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SubJobArgs {
     pub jid: String,
     pub address: String,
@@ -62,11 +313,14 @@ pub struct SubJobArgs {
     pub cover: String,
     pub shipping: String,
     pub pages: String,
-    pub cost: String,
-    pub cost_vat: String,
-    pub date: String,
-    pub dispatchdate: Option<String>,
-    pub sentdate: Option<String>,
+    pub cost: Money,
+    pub cost_vat: Money,
+    #[serde(with = "crate::lxpdate::date_time")]
+    pub date: DateTime<Utc>,
+    #[serde(default, with = "crate::lxpdate::option_date")]
+    pub dispatchdate: Option<NaiveDate>,
+    #[serde(default, with = "crate::lxpdate::option_date_time")]
+    pub sentdate: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -75,6 +329,19 @@ pub struct SubLetterData {
     pub base64_checksum: String,
     pub address: String,
     pub specification: SubSpecification,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::lxpdate::option_date"
+    )]
+    pub dispatchdate: Option<NaiveDate>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct SubLetterCreated {
+    #[serde(deserialize_with = "de_id")]
+    pub job_id: String,
+    pub status: Option<String>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -105,7 +372,9 @@ pub struct Response {
     pub balance: Option<SubBalance>,
     pub invoice: Option<SubInvoice>,
     pub invoices: Option<HashMap<String, SubInvoice>>,
+    pub job: Option<SubJobArgs>,
     pub jobs: Option<HashMap<String, SubJobArgs>>,
+    pub letter: Option<SubLetterCreated>,
     pub status: i32,
     pub message: String,
 }
@@ -1,12 +1,13 @@
 mod clidef;
 mod logger;
-mod lxpapi;
 mod lxpcommands;
 mod lxpconfig;
-mod lxptypes;
+mod lxpledger;
 
-use log::{info, debug};
+use log::{info, debug, error};
+use std::str::FromStr;
 use clap::{crate_name, crate_version};
+use lxpapi::lxptypes;
 
 #[tokio::main]
 async fn main() {
@@ -15,7 +16,7 @@ async fn main() {
 
     let verbose_level = matches.occurrences_of("verbose");
 
-    let (log_dir, config_dir) = match matches.subcommand_matches("watch-dir") {
+    let (log_dir, mut config_dir) = match matches.subcommand_matches("watch-dir") {
         Some(matches) => {
             let log_dir = std::fs::canonicalize(matches.value_of("directory")
                 .unwrap()) // CLAP ensures that
@@ -32,31 +33,40 @@ async fn main() {
             (log_dir, config_dir)
         },
     };
+    // The config dir can be overridden, e.g. to run watch-dir without root
+    if let Some(dir) = std::env::var_os("LXP_CONFIG_DIR") {
+        config_dir = std::path::PathBuf::from(dir);
+    }
 
     logger::init(crate_name!(), &log_dir, verbose_level);
+    let sandbox = matches.is_present("sandbox")
+        || matches
+            .subcommand()
+            .1
+            .is_some_and(|sub_matches| sub_matches.is_present("sandbox"));
+    let limit_args = lxpcommands::LimitArgs {
+        concurrency: limit_arg(&matches, "concurrency"),
+        requests_per_second: limit_arg(&matches, "requests_per_second").inspect(|rps: &f64| {
+            if !rps.is_finite() || *rps <= 0.0 {
+                error!("Invalid value '{}' for --requests_per_second, it must be above 0", rps);
+            }
+        }),
+        connect_timeout: limit_arg(&matches, "connect_timeout"),
+        request_timeout: limit_arg(&matches, "request_timeout"),
+    };
+    let mut lxp_cmds = lxpcommands::LxpCommands::new(&config_dir, limit_args, sandbox);
+    // All output of a run against the test environment is marked
+    if lxp_cmds.is_sandbox() {
+        logger::mark_sandbox();
+    }
     info!("{} {}", crate_name!(), crate_version!());
     debug!("log_dir {:?}", log_dir);
     debug!("config_dir {:?}", config_dir);
 
-    let mut lxp_cmds = lxpcommands::LxpCommands::new(&config_dir);
-
     // handle subcommand watch-dir
     if let Some(matches) = matches.subcommand_matches("watch-dir") {
-        let color = match matches.is_present("black_and_white") {
-            true => lxptypes::ColorPrint::BlackAndWhite,
-            false => lxptypes::ColorPrint::Color, 
-        };
-        let mode = match matches.is_present("international") {
-            true => lxptypes::Mode::Duplex,
-            false => lxptypes::Mode::Simplex,
-        };
-        let ship = match matches.is_present("duplex") {
-            true => lxptypes::Ship::International,
-            false => lxptypes::Ship::National,
-        };
-//        let dir_name = &matches.value_of("directory").unwrap().to_string();
         lxp_cmds
-            .watch_dir(&log_dir, color, mode, ship)
+            .watch_dir(&log_dir, &set_args(matches))
             .await;
     }
 
@@ -68,7 +78,11 @@ async fn main() {
                 matches.value_of("user").unwrap(),    // ...
                 matches.value_of("url").unwrap(),
                 matches.value_of("api_key").unwrap(),
-            );
+                matches.is_present("test"),
+            )
+            .await;
+        } else if matches.is_present("test") {
+            lxp_cmds.profile_test(matches.value_of("profile")).await;
         }
         if matches.is_present("delete") {
             // unwrap is ok, arg reqired
@@ -108,6 +122,41 @@ async fn main() {
             lxp_cmds.job_overview().await;
         }
 
+        // show a single job
+        if matches.is_present("show") {
+            lxp_cmds
+                .job_show(matches.value_of("show").unwrap())
+                .await;
+        }
+
+        // change the print options of a job
+        if matches.is_present("update") {
+            let color = if matches.is_present("black_and_white") {
+                Some(lxptypes::ColorPrint::BlackAndWhite)
+            } else if matches.is_present("color") {
+                Some(lxptypes::ColorPrint::Color)
+            } else {
+                None
+            };
+            let mode = if matches.is_present("duplex") {
+                Some(lxptypes::Mode::Duplex)
+            } else if matches.is_present("simplex") {
+                Some(lxptypes::Mode::Simplex)
+            } else {
+                None
+            };
+            let ship = if matches.is_present("international") {
+                Some(lxptypes::Ship::International)
+            } else if matches.is_present("national") {
+                Some(lxptypes::Ship::National)
+            } else {
+                None
+            };
+            lxp_cmds
+                .job_update(matches.value_of("update").unwrap(), color, mode, ship)
+                .await;
+        }
+
         // delete job(s)
         if matches.is_present("delete") {
             if matches.is_present("all") {
@@ -118,25 +167,172 @@ async fn main() {
                     .await;
             }
         }
+
+        // wait until jobs are sent
+        if matches.is_present("track") {
+            let mut ids: Vec<i32> = matches
+                .values_of("track")
+                .into_iter()
+                .flatten()
+                .map(|value| match value.parse() {
+                    Ok(id) => id,
+                    Err(_) => {
+                        error!("Invalid job id '{}' for --track", value);
+                        0
+                    }
+                })
+                .collect();
+            // without ids the jobs of the history that may still change
+            if ids.is_empty() {
+                ids = lxp_cmds.open_jobs();
+            }
+            let code = lxp_cmds.job_track(&ids, &track_args(matches)).await;
+            std::process::exit(code);
+        }
+    }
+
+    // handle subcommand sync
+    if let Some(matches) = matches.subcommand_matches("sync") {
+        let days = match matches.value_of("days").map(|value| (value, value.parse::<i32>())) {
+            None => 30,
+            Some((_, Ok(days))) if days > 0 => days,
+            Some((value, _)) => {
+                error!("Invalid value '{}' for --days", value);
+                0
+            }
+        };
+        lxp_cmds.sync(days).await;
     }
 
     // handle subcommand set
     if let Some(matches) = matches.subcommand_matches("set") {
-        let color = match matches.is_present("black_and_white") {
-            true => lxptypes::ColorPrint::BlackAndWhite,
-            false => lxptypes::ColorPrint::Color, 
-        };
-        let mode = match matches.is_present("international") {
-            true => lxptypes::Mode::Duplex,
-            false => lxptypes::Mode::Simplex,
-        };
-        let ship = match matches.is_present("duplex") {
-            true => lxptypes::Ship::International,
-            false => lxptypes::Ship::National,
+        let args = set_args(matches);
+        let file_names: Vec<&str> = matches.values_of("file_or_dir").unwrap().collect();
+        let estimate = matches.is_present("estimate");
+        if matches.is_present("merge") {
+            match estimate {
+                true => lxp_cmds.job_estimate_merged(&file_names, &args).await,
+                false => lxp_cmds.job_set_merged(&file_names, &args).await,
+            }
+        } else if file_names.len() > 1 {
+            error!("Several files are only sent with --merge");
+        } else if estimate {
+            lxp_cmds
+                .job_estimate_file_or_dir(file_names[0], &args)
+                .await;
+        } else {
+            lxp_cmds
+                .job_set_file_or_dir(file_names[0], &args)
+                .await;
+        }
+        if matches.is_present("track") {
+            let ids = lxp_cmds.session_jobs();
+            let code = lxp_cmds.job_track(&ids, &track_args(matches)).await;
+            std::process::exit(code);
+        }
+    }
+}
+
+// Global args may be given before or after the subcommand
+fn limit_arg<T: FromStr>(matches: &clap::ArgMatches, name: &str) -> Option<T> {
+    let value = matches
+        .subcommand()
+        .1
+        .and_then(|sub_matches| sub_matches.value_of(name))
+        .or_else(|| matches.value_of(name))?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            error!("Invalid value '{}' for --{}", value, name);
+            None
+        }
+    }
+}
+
+// Options of the letters uploaded by set and watch-dir
+fn set_args(matches: &clap::ArgMatches) -> lxpcommands::SetArgs {
+    let color = match matches.is_present("black_and_white") {
+        true => lxptypes::ColorPrint::BlackAndWhite,
+        false => lxptypes::ColorPrint::Color,
+    };
+    let mode = match matches.is_present("duplex") {
+        true => lxptypes::Mode::Duplex,
+        false => lxptypes::Mode::Simplex,
+    };
+    let ship = match matches.is_present("international") {
+        true => lxptypes::Ship::International,
+        false => lxptypes::Ship::National,
+    };
+    lxpcommands::SetArgs {
+        color,
+        mode,
+        ship,
+        dispatch_date: dispatch_date(matches),
+        split: split_arg(matches),
+        // clap only accepts the possible values
+        address_check: matches
+            .value_of("address_check")
+            .and_then(|value| value.parse().ok()),
+        force: matches.is_present("force"),
+    }
+}
+
+// Polling of job --track and set --track
+fn track_args(matches: &clap::ArgMatches) -> lxpcommands::TrackArgs {
+    let seconds = |name| {
+        let value = matches.value_of(name)?;
+        match value.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => Some(std::time::Duration::from_secs(seconds)),
+            _ => {
+                error!("Invalid value '{}' for --{}", value, name);
+                None
+            }
+        }
+    };
+    lxpcommands::TrackArgs {
+        interval: seconds("interval").unwrap_or_else(|| std::time::Duration::from_secs(60)),
+        timeout: seconds("timeout"),
+    }
+}
+
+// A PDF file may contain several letters
+fn split_arg(matches: &clap::ArgMatches) -> Option<lxpcommands::SplitArg> {
+    if let Some(value) = matches.value_of("split_pages") {
+        return match value.parse::<u32>() {
+            Ok(pages) if pages > 0 => Some(lxpcommands::SplitArg::Pages(pages)),
+            _ => {
+                error!("Invalid value '{}' for --split_pages", value);
+                None
+            }
         };
-        let file_or_dir_name = matches.value_of("file_or_dir").unwrap().to_string();
-        lxp_cmds
-            .job_set_file_or_dir(&file_or_dir_name, color, mode, ship)
-            .await;
+    }
+    if matches.is_present("split_ranges") {
+        return Some(lxpcommands::SplitArg::Ranges);
+    }
+    if let Some(marker) = matches.value_of("split_text") {
+        return Some(lxpcommands::SplitArg::Text(marker.into()));
+    }
+    if matches.is_present("split_blank") {
+        return Some(lxpcommands::SplitArg::BlankPage);
+    }
+    None
+}
+
+// A letter may be scheduled for a later day
+fn dispatch_date(matches: &clap::ArgMatches) -> Option<chrono::NaiveDate> {
+    let value = matches.value_of("dispatch_date")?;
+    let date = match chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) => date,
+        Err(e) => {
+            error!("Invalid dispatch date '{}', expected YYYY-MM-DD: {}", value, e);
+            return None;
+        }
+    };
+    match lxpapi::validate_dispatch_date(date) {
+        Ok(date) => Some(date),
+        Err(e) => {
+            error!("{}", e);
+            None
+        }
     }
 }
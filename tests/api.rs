@@ -0,0 +1,653 @@
+mod common;
+
+use common::{Fault, MockServer, TempDir, API_KEY, USER};
+use lxpapi::lxptypes::{ColorPrint, JobStatus, Mode, Ship};
+use lxpapi::{AddressCheck, AddressProblem, AddressWindow, Connection, Limits, LxpApi};
+use lxpapi::{LxpApiError, Money, RetryPolicy, Tariff};
+use chrono::{NaiveDate, TimeZone, Utc};
+use std::time::{Duration, Instant};
+
+fn api(server: &MockServer) -> LxpApi {
+    let retry = RetryPolicy {
+        base_delay: Duration::from_millis(10),
+        ..Default::default()
+    };
+    LxpApi::new(USER, API_KEY, &server.url).with_retry_policy(retry)
+}
+
+#[tokio::test]
+async fn get_balance() {
+    let server = MockServer::start();
+    let balance = api(&server).get_balance().await.unwrap();
+    assert_eq!(balance.value, Money::from_cents(9814, "EUR"));
+}
+
+#[tokio::test]
+async fn wrong_api_key_is_unauthorized() {
+    let server = MockServer::start();
+    let api = LxpApi::new(USER, "wrong", &server.url);
+    match api.get_balance().await {
+        Err(LxpApiError::Unauthorized { .. }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn malformed_json_is_reported_with_body() {
+    let server = MockServer::start();
+    server.inject(Fault::MalformedJson);
+    let api = api(&server).with_retry_policy(RetryPolicy {
+        max_attempts: 1,
+        ..Default::default()
+    });
+    match api.list_invoices().await {
+        Err(LxpApiError::Json { body, .. }) => assert!(body.contains("Internal error")),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn transient_errors_are_retried() {
+    let server = MockServer::start();
+    server.inject(Fault::HttpStatus(503));
+    server.inject(Fault::HttpStatus(502));
+    server.inject(Fault::Slow(Duration::from_millis(200)));
+    let jobs = api(&server).get_jobs_hold().await.unwrap();
+    assert!(jobs.is_empty());
+    assert_eq!(server.state().count_requests("GET getJobs/hold"), 3);
+}
+
+#[tokio::test]
+async fn retries_are_limited() {
+    let server = MockServer::start();
+    for _ in 0..4 {
+        server.inject(Fault::HttpStatus(500));
+    }
+    match api(&server).get_jobs_hold().await {
+        Err(LxpApiError::Http { status, .. }) => assert_eq!(status.as_u16(), 500),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn set_job_is_not_sent_twice() {
+    let server = MockServer::start();
+    let dir = TempDir::new("api-set-twice");
+    let pdf = dir.write_pdf("letter.pdf", 1);
+
+    // The first attempt fails after the job was created on the server
+    server.state().add_job("letter.pdf", "queue");
+    server.inject(Fault::HttpStatus(500));
+    let r = api(&server)
+        .set_job(
+            pdf.to_str().unwrap(),
+            &ColorPrint::Color,
+            &Mode::Simplex,
+            &Ship::National,
+            None,
+        )
+        .await;
+    match r {
+        Err(LxpApiError::Unconfirmed { job_ids, .. }) => assert_eq!(job_ids.len(), 1),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert_eq!(server.state().count_requests("POST setJob"), 1);
+}
+
+#[tokio::test]
+async fn set_job_ignores_other_letters_with_the_same_name() {
+    let server = MockServer::start();
+    let dir = TempDir::new("api-set-same-name");
+    let pdf = dir.write_pdf("letter.pdf", 2);
+
+    // A waiting job with the same name, but another page count
+    let other = server.state().add_job("letter.pdf", "queue") as i32;
+    server.inject(Fault::HttpStatus(500));
+    let created = api(&server)
+        .set_job(pdf.to_str().unwrap(), &ColorPrint::Color, &Mode::Simplex, &Ship::National, None)
+        .await
+        .unwrap();
+    assert_ne!(created.id, other);
+    assert_eq!(server.state().count_requests("POST setJob"), 2);
+}
+
+#[tokio::test]
+async fn set_job_is_not_repeated_without_answer() {
+    let server = MockServer::start();
+    let dir = TempDir::new("api-set-timeout");
+    let pdf = dir.write_pdf("letter.pdf", 1);
+
+    server.inject(Fault::Slow(Duration::from_secs(1)));
+    let api = api(&server).with_limits(Limits {
+        request_timeout: Duration::from_millis(200),
+        ..Default::default()
+    });
+    let r = api
+        .set_job(pdf.to_str().unwrap(), &ColorPrint::Color, &Mode::Simplex, &Ship::National, None)
+        .await;
+    match r {
+        Err(LxpApiError::Unconfirmed { job_ids, source }) => {
+            assert!(job_ids.is_empty());
+            assert!(matches!(*source, LxpApiError::Transport(ref e) if e.is_timeout()));
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert_eq!(server.state().count_requests("POST setJob"), 1);
+}
+
+#[tokio::test]
+async fn set_job_is_retried_if_safe() {
+    let server = MockServer::start();
+    let dir = TempDir::new("api-set-retry");
+    let pdf = dir.write_pdf("letter.pdf", 2);
+
+    server.inject(Fault::HttpStatus(503));
+    let created = api(&server)
+        .set_job(
+            pdf.to_str().unwrap(),
+            &ColorPrint::BlackAndWhite,
+            &Mode::Duplex,
+            &Ship::National,
+            None,
+        )
+        .await
+        .unwrap();
+    let queue = server.state().jobs_with_status("queue");
+    assert_eq!(queue.len(), 1);
+    assert_eq!(queue[0].id as i32, created.id);
+    assert_eq!(queue[0].pages, 2);
+    assert_eq!(queue[0].mode, "duplex");
+}
+
+#[tokio::test]
+async fn delete_unknown_job() {
+    let server = MockServer::start();
+    match api(&server).delete_job(4711).await {
+        Err(LxpApiError::NotFound { .. }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn get_invoice() {
+    let server = MockServer::start();
+    let invoice = api(&server).get_invoice(11328).await.unwrap();
+    assert_eq!(invoice.meta.id, 11328);
+    assert_eq!(invoice.meta.date, NaiveDate::from_ymd(2019, 1, 31));
+    assert!(invoice.pdf.starts_with(b"%PDF-"));
+}
+
+#[tokio::test]
+async fn jobs_are_typed() {
+    let server = MockServer::start();
+    server.state().add_job("letter1.pdf", "sent");
+    server.state().add_job("letter2.pdf", "sent");
+    let jobs = api(&server).get_jobs_sent(7).await.unwrap();
+    assert_eq!(jobs.len(), 2);
+    assert!(jobs[0].id < jobs[1].id);
+    assert_eq!(jobs[0].address, "letter1.pdf");
+    assert_eq!(jobs[0].color, ColorPrint::Color);
+    assert_eq!(jobs[0].mode, Mode::Simplex);
+    assert_eq!(jobs[0].shipping, Ship::National);
+    assert_eq!(jobs[0].pages, 1);
+    assert_eq!(&jobs[0].cost + &jobs[0].cost_vat, Money::from_cents(93, "EUR"));
+    // 2020-12-10 09:45:31 in German winter time
+    assert_eq!(jobs[0].date, Utc.ymd(2020, 12, 10).and_hms(8, 45, 31));
+    assert_eq!(jobs[0].sentdate, Some(Utc.ymd(2020, 12, 11).and_hms(13, 2, 10)));
+    assert_eq!(jobs[0].dispatchdate, None);
+}
+
+#[tokio::test]
+async fn invoices_are_ordered_by_date() {
+    let server = MockServer::start();
+    let invoices = api(&server).list_invoices().await.unwrap();
+    let dates: Vec<_> = invoices.iter().map(|invoice| invoice.date).collect();
+    assert_eq!(
+        dates,
+        vec![NaiveDate::from_ymd(2019, 1, 31), NaiveDate::from_ymd(2020, 10, 31)]
+    );
+}
+
+#[tokio::test]
+async fn invoice_without_pdf_is_invalid() {
+    let server = MockServer::start();
+    server.inject(Fault::Json(serde_json::json!({
+        "status": 200,
+        "message": "OK",
+        "invoice": { "iid": "30711", "invoicedate": "2020-10-31", "sum": "1.00", "vat": "0.19" },
+    })));
+    match api(&server).get_last_invoice().await {
+        Err(LxpApiError::Invalid { .. }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn set_job_checks_address_window() {
+    let server = MockServer::start();
+    let letter = common::pdf_letter(&["Max Mustermann", "12345 Berlin"]);
+    let set = |api: LxpApi, pdf: Vec<u8>| async move {
+        api.set_job_from_bytes("letter.pdf", pdf, &ColorPrint::Color, &Mode::Simplex, &Ship::National, None)
+            .await
+    };
+
+    let created = set(api(&server), letter.clone()).await.unwrap();
+    assert_eq!(created.recipient, vec!["Max Mustermann", "12345 Berlin"]);
+
+    // An empty window only gives a warning by default
+    let created = set(api(&server), common::pdf_document(1)).await.unwrap();
+    assert!(created.recipient.is_empty());
+
+    let strict = api(&server).with_address_check(AddressCheck::Reject, AddressWindow::din5008_b());
+    match set(strict.clone(), common::pdf_document(1)).await {
+        Err(LxpApiError::Address { problem, .. }) => assert_eq!(problem, AddressProblem::Empty),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert!(set(strict, letter.clone()).await.is_ok());
+
+    let off = api(&server).with_address_check(AddressCheck::Off, AddressWindow::din5008_b());
+    assert!(set(off, letter).await.unwrap().recipient.is_empty());
+    assert_eq!(server.state().count_requests("POST setJob"), 4);
+}
+
+#[tokio::test]
+async fn set_job_from_memory() {
+    let server = MockServer::start();
+    let api = api(&server);
+    let pdf = common::pdf_document(2);
+
+    api.set_job_from_bytes(
+        "Invoice 2020-0815",
+        pdf.clone(),
+        &ColorPrint::Color,
+        &Mode::Simplex,
+        &Ship::International,
+        None,
+    )
+    .await
+    .unwrap();
+    api.set_job_from_reader(
+        "Reminder 2020-0815",
+        std::io::Cursor::new(pdf),
+        &ColorPrint::Color,
+        &Mode::Simplex,
+        &Ship::National,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let queue = server.state().jobs_with_status("queue");
+    assert_eq!(queue.len(), 2);
+    assert_eq!(queue[0].address, "Invoice 2020-0815");
+    assert_eq!(queue[0].shipping, "international");
+    assert_eq!(queue[1].address, "Reminder 2020-0815");
+    assert_eq!(queue[1].pages, 2);
+}
+
+#[test]
+fn estimate_job_with_tariff() {
+    let tariff = Tariff {
+        page_black_and_white: "0.03".parse().unwrap(),
+        vat_percent: 7,
+        ..Default::default()
+    };
+    let api = LxpApi::new(USER, API_KEY, "http://127.0.0.1:1/v1/").with_tariff(tariff);
+    let estimate = api
+        .estimate_job_from_bytes(
+            "letter",
+            &common::pdf_document(4),
+            &ColorPrint::BlackAndWhite,
+            &Mode::Duplex,
+            &Ship::National,
+        )
+        .unwrap();
+    assert_eq!(estimate.pages, 4);
+    assert_eq!(estimate.sheets, 2);
+    assert_eq!(estimate.cost, Money::from_cents(82, "EUR"));
+    assert_eq!(estimate.cost_vat, Money::from_cents(6, "EUR"));
+
+    let invalid = api.estimate_job_from_bytes(
+        "notes",
+        b"no pdf",
+        &ColorPrint::Color,
+        &Mode::Simplex,
+        &Ship::National,
+    );
+    match invalid {
+        Err(LxpApiError::InvalidPdf { .. }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn hung_request_times_out() {
+    let server = MockServer::start();
+    server.inject(Fault::Slow(Duration::from_secs(1)));
+    let api = api(&server)
+        .with_retry_policy(RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        })
+        .with_limits(Limits {
+            request_timeout: Duration::from_millis(200),
+            ..Default::default()
+        });
+    let start = Instant::now();
+    match api.get_balance().await {
+        Err(LxpApiError::Transport(e)) => assert!(e.is_timeout()),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert!(start.elapsed() < Duration::from_millis(800));
+}
+
+#[tokio::test]
+async fn requests_are_limited() {
+    let server = MockServer::start();
+    server.inject(Fault::Slow(Duration::from_millis(300)));
+    server.inject(Fault::Slow(Duration::from_millis(300)));
+    let api = api(&server).with_limits(Limits {
+        max_concurrent: 1,
+        ..Default::default()
+    });
+    let start = Instant::now();
+    let (a, b) = tokio::join!(api.get_balance(), api.get_balance());
+    a.unwrap();
+    b.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(600));
+
+    let api = api.with_limits(Limits {
+        requests_per_second: Some(10.0),
+        ..Default::default()
+    });
+    let start = Instant::now();
+    for _ in 0..4 {
+        api.get_balance().await.unwrap();
+    }
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn requests_through_proxy() {
+    let server = MockServer::start();
+    let proxy = server.url.trim_end_matches("v1/").to_string();
+    let connection = Connection {
+        proxy: Some(proxy),
+        proxy_user: Some("office".into()),
+        proxy_password: Some("secret".into()),
+        ..Default::default()
+    };
+    let api = LxpApi::new(USER, API_KEY, "http://api.letterxpress.invalid/v1/")
+        .with_connection(&connection)
+        .unwrap();
+    api.get_balance().await.unwrap();
+    let (host, auth) = server.state().proxied.clone().unwrap();
+    assert_eq!(host, "api.letterxpress.invalid");
+    assert_eq!(auth, format!("Basic {}", base64::encode("office:secret")));
+}
+
+#[test]
+fn unusable_connection_settings() {
+    let dir = TempDir::new("api-connection");
+    let ca = dir.path.join("ca.pem");
+    std::fs::write(&ca, "-----BEGIN CERTIFICATE-----\nbroken\n").unwrap();
+    let settings = vec![
+        Connection {
+            proxy: Some("no proxy url".into()),
+            ..Default::default()
+        },
+        Connection {
+            ca_certificates: vec![dir.path.join("missing.pem")],
+            ..Default::default()
+        },
+        Connection {
+            ca_certificates: vec![ca],
+            ..Default::default()
+        },
+    ];
+    for connection in &settings {
+        match LxpApi::new(USER, API_KEY, "https://localhost/v1/").with_connection(connection) {
+            Err(LxpApiError::Connection { .. }) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+}
+
+#[tokio::test]
+async fn get_single_job() {
+    let server = MockServer::start();
+    server.state().add_job("letter1.pdf", "hold");
+    let id = server.state().add_job("letter2.pdf", "sent") as i32;
+    let api = api(&server);
+
+    let job = api.get_job(id).await.unwrap();
+    assert_eq!(job.address, "letter2.pdf");
+    assert_eq!(job.status, JobStatus::Sent);
+    // The amounts are in the currency of the account, asked for once
+    assert_eq!(job.cost.currency(), "EUR");
+    assert_eq!(server.state().requests, vec![format!("GET getJob/{}", id), "GET getBalance".into()]);
+
+    // Without the job endpoint the job lists are searched
+    server.state().job_endpoint = false;
+    let job = api.get_job(id).await.unwrap();
+    assert_eq!(job.id, id);
+    match api.get_job(4711).await {
+        Err(LxpApiError::NotFound { .. }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert_eq!(server.state().count_requests("GET getBalance"), 1);
+}
+
+#[tokio::test]
+async fn amounts_in_account_currency() {
+    let server = MockServer::start();
+    server.state().currency = "CHF".into();
+    server.state().add_job("letter.pdf", "queue");
+    let api = api(&server);
+
+    let jobs = api.get_jobs_queue(7).await.unwrap();
+    assert_eq!(jobs[0].cost.currency(), "CHF");
+    assert_eq!(jobs[0].cost_vat.currency(), "CHF");
+    let invoices = api.list_invoices().await.unwrap();
+    assert!(invoices.iter().all(|invoice| invoice.sum.currency() == "CHF"));
+    assert_eq!(api.get_last_invoice().await.unwrap().meta.vat.currency(), "CHF");
+}
+
+#[tokio::test]
+async fn set_job_with_dispatch_date() {
+    let server = MockServer::start();
+    let api = api(&server);
+    let pdf = common::pdf_document(1);
+    let monday = Utc::today().naive_utc() + chrono::Duration::days(3);
+
+    api.set_job_from_bytes(
+        "Reminder",
+        pdf.clone(),
+        &ColorPrint::Color,
+        &Mode::Simplex,
+        &Ship::National,
+        Some(monday),
+    )
+    .await
+    .unwrap();
+    let jobs = api.get_jobs_queue(7).await.unwrap();
+    assert_eq!(jobs[0].dispatchdate, Some(monday));
+
+    let yesterday = Utc::today().naive_utc().pred();
+    let r = api
+        .set_job_from_bytes(
+            "Reminder",
+            pdf,
+            &ColorPrint::Color,
+            &Mode::Simplex,
+            &Ship::National,
+            Some(yesterday),
+        )
+        .await;
+    match r {
+        Err(LxpApiError::DispatchDate { date }) => assert_eq!(date, yesterday),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert_eq!(server.state().count_requests("POST setJob"), 1);
+}
+
+#[tokio::test]
+async fn update_job() {
+    let server = MockServer::start();
+    let id = server.state().add_job("letter1.pdf", "queue") as i32;
+    let updated = api(&server)
+        .update_job(id, &ColorPrint::BlackAndWhite, &Mode::Duplex, &Ship::National, None)
+        .await
+        .unwrap();
+    assert_eq!(updated.id, id);
+    assert!(!updated.resubmitted);
+    let job = server.state().jobs[&(id as u32)].clone();
+    assert_eq!(job.color, 1);
+    assert_eq!(job.mode, "duplex");
+}
+
+#[tokio::test]
+async fn update_job_by_resubmitting() {
+    let server = MockServer::start();
+    server.state().update_endpoint = false;
+    let api = api(&server);
+    let pdf = common::pdf_document(2);
+    let created = api
+        .set_job_from_bytes(
+            "letter1.pdf",
+            pdf.clone(),
+            &ColorPrint::Color,
+            &Mode::Simplex,
+            &Ship::National,
+            None,
+        )
+        .await
+        .unwrap();
+
+    // Without a copy the job can't be changed
+    let r = api
+        .update_job(created.id, &ColorPrint::BlackAndWhite, &Mode::Simplex, &Ship::National, None)
+        .await;
+    match r {
+        Err(LxpApiError::Unsupported { .. }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+    // A copy that does not fit is refused
+    let r = api
+        .update_job(
+            created.id,
+            &ColorPrint::BlackAndWhite,
+            &Mode::Simplex,
+            &Ship::National,
+            Some(common::pdf_document(3)),
+        )
+        .await;
+    match r {
+        Err(LxpApiError::Conflict { .. }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+    // A copy the address check rejects keeps the job
+    let r = api
+        .clone()
+        .with_address_check(AddressCheck::Reject, AddressWindow::din5008_b())
+        .update_job(
+            created.id,
+            &ColorPrint::BlackAndWhite,
+            &Mode::Simplex,
+            &Ship::National,
+            Some(pdf.clone()),
+        )
+        .await;
+    match r {
+        Err(LxpApiError::Address { .. }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert_eq!(server.state().count_requests(&format!("DELETE deleteJob/{}", created.id)), 0);
+
+    let updated = api
+        .update_job(
+            created.id,
+            &ColorPrint::BlackAndWhite,
+            &Mode::Simplex,
+            &Ship::National,
+            Some(pdf),
+        )
+        .await
+        .unwrap();
+    assert!(updated.resubmitted);
+    let state = server.state();
+    assert!(!state.jobs.contains_key(&(created.id as u32)));
+    let job = &state.jobs[&(updated.id as u32)];
+    assert_eq!(job.address, "letter1.pdf");
+    assert_eq!(job.color, 1);
+}
+
+#[tokio::test]
+async fn update_job_keeps_job_when_server_fails() {
+    let server = MockServer::start();
+    let id = server.state().add_job("letter1.pdf", "queue") as i32;
+    // The job is found, then the update fails on every attempt
+    server.inject(Fault::Slow(Duration::from_millis(1)));
+    for _ in 0..4 {
+        server.inject(Fault::HttpStatus(503));
+    }
+    let r = api(&server)
+        .update_job(
+            id,
+            &ColorPrint::BlackAndWhite,
+            &Mode::Simplex,
+            &Ship::National,
+            Some(common::pdf_document(1)),
+        )
+        .await;
+    match r {
+        Err(LxpApiError::Http { status, .. }) => assert_eq!(status.as_u16(), 503),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert_eq!(server.state().count_requests(&format!("DELETE deleteJob/{}", id)), 0);
+    assert!(server.state().jobs.contains_key(&(id as u32)));
+}
+
+#[tokio::test]
+async fn sent_job_can_not_be_updated() {
+    let server = MockServer::start();
+    let id = server.state().add_job("letter1.pdf", "sent") as i32;
+    let r = api(&server)
+        .update_job(id, &ColorPrint::BlackAndWhite, &Mode::Simplex, &Ship::National, None)
+        .await;
+    match r {
+        Err(LxpApiError::Conflict { .. }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert_eq!(server.state().count_requests(&format!("PUT updateJob/{}", id)), 0);
+}
+
+#[tokio::test]
+async fn check_access() {
+    let server = MockServer::start();
+    let check = api(&server).check_access().await.unwrap();
+    assert_eq!(check.user.as_deref(), Some(USER));
+    assert_eq!(check.account_id.as_deref(), Some("4711"));
+    assert_eq!(check.balance, Money::from_cents(9814, "EUR"));
+
+    // Not retried, the first failure is reported
+    server.inject(Fault::HttpStatus(503));
+    match api(&server).check_access().await {
+        Err(LxpApiError::Http { status, .. }) => assert_eq!(status.as_u16(), 503),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert_eq!(server.state().count_requests("GET getBalance"), 2);
+}
+
+#[tokio::test]
+async fn sandbox_replaces_url() {
+    let server = MockServer::start();
+    let sandbox = MockServer::start();
+    let api = api(&server);
+    assert!(!api.is_sandbox());
+    let api = api.with_sandbox(&sandbox.url);
+    assert!(api.is_sandbox());
+    api.get_balance().await.unwrap();
+    assert_eq!(sandbox.state().count_requests("GET getBalance"), 1);
+    assert_eq!(server.state().count_requests("GET getBalance"), 0);
+}
@@ -0,0 +1,71 @@
+// The synchronous client, run with --features blocking
+mod common;
+
+use common::{Fault, MockServer, API_KEY, USER};
+use lxpapi::blocking::LxpApi;
+use lxpapi::lxptypes::{ColorPrint, Mode, Ship};
+use lxpapi::{LxpApiError, Money, RetryPolicy};
+use std::time::Duration;
+
+fn api(server: &MockServer) -> LxpApi {
+    let retry = RetryPolicy {
+        base_delay: Duration::from_millis(10),
+        ..Default::default()
+    };
+    LxpApi::new(USER, API_KEY, &server.url).with_retry_policy(retry)
+}
+
+#[test]
+fn get_balance_and_jobs() {
+    let server = MockServer::start();
+    server.state().add_job("letter1.pdf", "hold");
+    let api = api(&server);
+    assert_eq!(api.get_balance().unwrap().value, Money::from_cents(9814, "EUR"));
+    let jobs = api.get_jobs_hold().unwrap();
+    assert_eq!(jobs[0].address, "letter1.pdf");
+}
+
+#[test]
+fn set_job_with_retry() {
+    let server = MockServer::start();
+    server.inject(Fault::HttpStatus(503));
+    let created = api(&server)
+        .set_job_from_reader(
+            "letter",
+            std::io::Cursor::new(common::pdf_document(2)),
+            &ColorPrint::Color,
+            &Mode::Simplex,
+            &Ship::National,
+            None,
+        )
+        .unwrap();
+    let queue = server.state().jobs_with_status("queue");
+    assert_eq!(queue[0].id as i32, created.id);
+    assert_eq!(queue[0].pages, 2);
+}
+
+#[test]
+fn shared_between_threads() {
+    let server = MockServer::start();
+    let api = api(&server);
+    let threads: Vec<_> = (0..3)
+        .map(|_| {
+            let api = api.clone();
+            std::thread::spawn(move || api.get_balance().unwrap())
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(server.state().count_requests("GET getBalance"), 3);
+}
+
+#[test]
+fn errors_are_the_same() {
+    let server = MockServer::start();
+    server.inject(Fault::BadAuth);
+    match api(&server).get_balance() {
+        Err(LxpApiError::Unauthorized { .. }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
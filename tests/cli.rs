@@ -0,0 +1,803 @@
+// End-to-end tests of the lxp binary against the mock server
+mod common;
+
+use common::{pdf_document_with_texts, pdf_letter, stderr, stdout, wait_for, Fault, Lxp, MockServer, API_KEY, USER};
+use std::process::Stdio;
+use std::time::Duration;
+
+#[test]
+fn set_single_file() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-set-file", &server);
+    lxp.dir.write_pdf("work/letter1.pdf", 3);
+
+    let output = lxp.run(&["set", "-b", "letter1.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Job letter1.pdf sent"));
+
+    let queue = server.state().jobs_with_status("queue");
+    assert_eq!(queue.len(), 1);
+    assert_eq!(queue[0].address, "letter1.pdf");
+    assert_eq!(queue[0].pages, 3);
+    assert_eq!(queue[0].color, 1);
+}
+
+#[test]
+fn trace_log_is_redacted() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-set-trace", &server);
+    lxp.dir.write_pdf("work/letter1.pdf", 1);
+
+    let output = lxp.run(&["-vv", "set", "letter1.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let log = std::fs::read_to_string(lxp.path("lxp.log")).unwrap();
+    assert!(log.contains("\"apikey\":\"***\""));
+    assert!(!log.contains(API_KEY));
+    // base64 of "%PDF-"
+    assert!(!log.contains("JVBERi0"));
+}
+
+#[test]
+fn set_directory() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-set-dir", &server);
+    for i in 1..=6 {
+        lxp.dir.write_pdf(&format!("work/pdf_dir/letter{}.pdf", i), 1);
+    }
+    std::fs::write(lxp.path("pdf_dir/notes.txt"), "no letter").unwrap();
+
+    let output = lxp.run(&["set", "pdf_dir"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).matches(" sent").count(), 6);
+    let queue = server.state().jobs_with_status("queue");
+    assert_eq!(queue.len(), 6);
+    let mut addresses: Vec<&str> = queue.iter().map(|job| job.address.as_str()).collect();
+    addresses.sort();
+    assert_eq!(addresses[0], "letter1.pdf");
+    assert_eq!(addresses[5], "letter6.pdf");
+}
+
+#[test]
+fn set_with_wrong_api_key() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-set-auth", &server);
+    lxp.dir.write_pdf("work/letter1.pdf", 1);
+
+    server.inject(Fault::BadAuth);
+    let output = lxp.run(&["set", "letter1.pdf"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("check user and apikey"));
+    assert!(server.state().jobs.is_empty());
+}
+
+#[test]
+fn set_invalid_pdf() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-set-invalid", &server);
+    std::fs::write(lxp.path("letter1.pdf"), b"PK\x03\x04 renamed word file").unwrap();
+
+    let output = lxp.run(&["set", "letter1.pdf"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("not a PDF document"));
+    assert!(server.state().requests.is_empty());
+}
+
+#[test]
+fn set_duplex_international() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-set-options", &server);
+    lxp.dir.write_pdf("work/letter1.pdf", 2);
+
+    let output = lxp.run(&["set", "-d", "-i", "letter1.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let queue = server.state().jobs_with_status("queue");
+    assert_eq!(queue[0].mode, "duplex");
+    assert_eq!(queue[0].shipping, "international");
+}
+
+#[test]
+fn set_with_limits() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-set-limits", &server);
+    for i in 1..=3 {
+        lxp.dir.write_pdf(&format!("work/pdf_dir/letter{}.pdf", i), 1);
+    }
+
+    let output = lxp.run(&["--concurrency", "1", "set", "pdf_dir", "--request_timeout", "30"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(server.state().jobs_with_status("queue").len(), 3);
+
+    let output = lxp.run(&["set", "pdf_dir", "--requests_per_second", "many"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Invalid value 'many' for --requests_per_second"));
+    let output = lxp.run(&["set", "pdf_dir", "--requests_per_second", "0"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("it must be above 0"));
+}
+
+#[test]
+fn set_with_dispatch_date() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-set-dispatch", &server);
+    lxp.dir.write_pdf("work/letter1.pdf", 1);
+    let date = (chrono::Local::today().naive_local() + chrono::Duration::days(3))
+        .format("%Y-%m-%d")
+        .to_string();
+
+    let output = lxp.run(&["set", "--dispatch_date", &date, "letter1.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = lxp.run(&["job", "-o"]);
+    assert!(stdout(&output).contains(&format!("2020-12-10 {}", date)));
+
+    let output = lxp.run(&["set", "--dispatch_date", "2020-12-24", "letter1.pdf"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Dispatch date 2020-12-24 is not in the future"));
+    assert_eq!(server.state().count_requests("POST setJob"), 1);
+}
+
+#[test]
+fn set_estimate() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-set-estimate", &server);
+    lxp.dir.write_pdf("work/pdf_dir/letter1.pdf", 3);
+    lxp.dir.write_pdf("work/pdf_dir/letter2.pdf", 1);
+
+    let output = lxp.run(&["set", "--estimate", "-d", "pdf_dir"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("     3      2   1.00 €   1.19 € letter1.pdf"));
+    assert!(out.contains("     1      1   0.78 €   0.93 € letter2.pdf"));
+    assert!(out.contains("The estimated sum of the costs is 2.12 €"));
+    assert!(out.contains("The credit is sufficient"));
+    assert_eq!(server.state().requests, vec!["GET getBalance"]);
+
+    server.state().balance = "1.00".into();
+    let output = lxp.run(&["set", "-e", "pdf_dir/letter1.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("The credit is insufficient, 0.21 € are missing"));
+
+    // The tariff is in euro, a balance in another currency is not compared
+    server.inject(Fault::Json(serde_json::json!({
+        "status": 200,
+        "message": "OK",
+        "balance": { "value": "50.00", "currency": "CHF" },
+    })));
+    let output = lxp.run(&["set", "-e", "pdf_dir/letter1.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Credit and costs not compared: amounts in EUR and CHF can't be combined"));
+}
+
+fn queued_letters(server: &MockServer) -> Vec<(String, u32)> {
+    let mut letters: Vec<(String, u32)> = server
+        .state()
+        .jobs_with_status("queue")
+        .iter()
+        .map(|job| (job.address.clone(), job.pages))
+        .collect();
+    letters.sort();
+    letters
+}
+
+#[test]
+fn set_split_by_pages_and_ranges() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-set-split", &server);
+    let pdf = pdf_document_with_texts(&["Letter A", "page 2", "Letter B", "page 2", "Letter C"]);
+    std::fs::write(lxp.path("run.pdf"), &pdf).unwrap();
+
+    let output = lxp.run(&["set", "--split_pages", "2", "run.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("File run.pdf split into 3 letters"));
+    assert_eq!(
+        queued_letters(&server),
+        vec![("run-001.pdf".into(), 2), ("run-002.pdf".into(), 2), ("run-003.pdf".into(), 1)]
+    );
+    let archive = lxp.dir.path.join("config/archive/test");
+    assert_eq!(std::fs::read_dir(&archive).unwrap().count(), 4);
+    assert!(archive.join("ledger.json").exists());
+
+    server.state().jobs.clear();
+    let output = lxp.run(&["set", "--split_ranges", "run.pdf"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("run.ranges"));
+
+    // Letter C was sent before
+    std::fs::write(lxp.path("run.ranges"), "1-4\n5\n").unwrap();
+    let output = lxp.run(&["set", "--split_ranges", "run.pdf", "--force"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        queued_letters(&server),
+        vec![("run-001.pdf".into(), 4), ("run-002.pdf".into(), 1)]
+    );
+}
+
+#[test]
+fn set_split_at_separators() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-set-split-separators", &server);
+    let pdf = pdf_document_with_texts(&["Letter A", "page 2", "", "Letter B", "##CUT##", "Letter C"]);
+    std::fs::create_dir_all(lxp.work_dir().join("pdf_dir")).unwrap();
+    std::fs::write(lxp.path("pdf_dir/run.pdf"), &pdf).unwrap();
+
+    let output = lxp.run(&["set", "--split_text", "##CUT##", "pdf_dir"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        queued_letters(&server),
+        vec![("run-001.pdf".into(), 4), ("run-002.pdf".into(), 1)]
+    );
+
+    let output = lxp.run(&["set", "--estimate", "--split_blank", "pdf_dir/run.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("     2      2   0.90 €   1.07 € run-001.pdf"), "{}", out);
+    assert!(out.contains("     3      3   1.02 €   1.21 € run-002.pdf"), "{}", out);
+
+    let output = lxp.run(&["set", "--split_blank", "--split_pages", "2", "pdf_dir"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn set_merge() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-set-merge", &server);
+    lxp.dir.write_pdf("work/cover.pdf", 1);
+    lxp.dir.write_pdf("work/contract.pdf", 3);
+    lxp.dir.write_pdf("work/terms.pdf", 2);
+
+    let output = lxp.run(&["set", "contract.pdf", "terms.pdf"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Several files are only sent with --merge"));
+
+    let output = lxp.run(&["set", "--merge", "-e", "cover.pdf", "contract.pdf", "terms.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("     6      6   1.38 €   1.64 € cover.pdf"));
+
+    let output = lxp.run(&["set", "--merge", "cover.pdf", "contract.pdf", "terms.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Job cover.pdf sent, merged from 3 files"));
+    assert_eq!(queued_letters(&server), vec![("cover.pdf".into(), 6)]);
+
+    std::fs::write(lxp.path("notes.txt"), "no pdf").unwrap();
+    let output = lxp.run(&["set", "-m", "cover.pdf", "notes.txt"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("notes.txt: no PDF header"));
+}
+
+#[test]
+fn set_shows_recipient() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-set-recipient", &server);
+    let letter = pdf_letter(&["Max Mustermann", "Musterstr. 1", "12345 Berlin"]);
+    std::fs::write(lxp.path("letter.pdf"), letter).unwrap();
+    lxp.dir.write_pdf("work/scan.pdf", 1);
+    lxp.dir.write_pdf("work/scan2.pdf", 1);
+
+    let output = lxp.run(&["set", "letter.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Job letter.pdf sent to Max Mustermann, Musterstr. 1, 12345 Berlin"));
+
+    let output = lxp.run(&["set", "--address_check", "reject", "scan.pdf"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Address check of 'scan.pdf' failed"));
+
+    let output = lxp.run(&["set", "scan.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("WARN - Letter 'scan.pdf': no address found in the address window"));
+
+    lxp.configure("test", "address_check = \"reject\"");
+    let output = lxp.run(&["set", "scan2.pdf"]);
+    assert!(!output.status.success());
+    let output = lxp.run(&["set", "--address_check", "off", "scan2.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(queued_letters(&server).len(), 3);
+}
+
+#[test]
+fn set_refuses_duplicates() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-set-duplicates", &server);
+    let pdf = lxp.dir.write_pdf("work/invoice.pdf", 1);
+    std::fs::copy(&pdf, lxp.path("copy.pdf")).unwrap();
+
+    let output = lxp.run(&["set", "invoice.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let output = lxp.run(&["set", "copy.pdf"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("the same content is waiting on the server as job 57451 (queue)"), "{}", stderr(&output));
+
+    // Sent jobs are no longer pending, the ledger still knows them
+    server.state().jobs.clear();
+    let output = lxp.run(&["set", "copy.pdf"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("the same content was sent as job 57451 on"));
+    assert!(stderr(&output).contains("--force"));
+
+    let output = lxp.run(&["set", "--force", "copy.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(queued_letters(&server), vec![("copy.pdf".into(), 1)]);
+
+    // Jobs sent from elsewhere are recognized by name and pages
+    server.state().add_job("other.pdf", "hold");
+    lxp.dir.write_pdf("work/other.pdf", 1);
+    let output = lxp.run(&["set", "other.pdf"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("with the same name is waiting on the server"));
+
+    server.state().jobs.clear();
+    lxp.configure("test", "duplicate_window_days = 0");
+    let output = lxp.run(&["set", "copy.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+fn sync_keeps_history() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-sync", &server);
+    lxp.dir.write_pdf("work/letter1.pdf", 1);
+    lxp.dir.write_pdf("work/letter2.pdf", 2);
+    for letter in &["letter1.pdf", "letter2.pdf"] {
+        let output = lxp.run(&["set", "-d", letter]);
+        assert!(output.status.success(), "{}", stderr(&output));
+    }
+    let history = || -> serde_json::Value {
+        let path = lxp.dir.path.join("config/archive/test/ledger.json");
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    };
+    let entries = history();
+    let entries = entries["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1]["address"], "letter2.pdf");
+    assert_eq!(entries[1]["pages"], 2);
+    assert_eq!(entries[1]["mode"], "duplex");
+    let file = entries[1]["files"][0].as_str().unwrap();
+    assert!(file.ends_with("work/letter2.pdf"), "{}", file);
+
+    {
+        let mut state = server.state();
+        let id = state.jobs.values().find(|job| job.address == "letter2.pdf").unwrap().id;
+        state.jobs.remove(&id);
+        state.jobs.values_mut().for_each(|job| job.status = "sent".into());
+        state.add_job("portal.pdf", "hold");
+    }
+    let archived = || {
+        std::fs::read_dir(lxp.dir.path.join("config/archive/test"))
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "pdf"))
+            .count()
+    };
+    assert_eq!(archived(), 2);
+    let output = lxp.run(&["sync"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Job history synchronized: 1 updated, 1 added, 1 deleted, 0 unknown"));
+    // The copies of sent and deleted jobs are not needed anymore
+    assert_eq!(archived(), 0);
+    let entries = history();
+    let status: Vec<(&str, &str)> = entries["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| (entry["address"].as_str().unwrap(), entry["status"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        status,
+        vec![("letter1.pdf", "sent"), ("letter2.pdf", "deleted"), ("portal.pdf", "hold")]
+    );
+    assert_eq!(entries["entries"][0]["sent_date"], "2020-12-11");
+
+    // The history outlives the job lists of the server. A job last seen
+    // before the lists reach back may have been sent, it is not deleted.
+    let mut entries = history();
+    entries["entries"][2]["synced"] = "2020-01-04T10:00:00+00:00".into();
+    let path = lxp.dir.path.join("config/archive/test/ledger.json");
+    std::fs::write(path, entries.to_string()).unwrap();
+    server.state().jobs.clear();
+    let output = lxp.run(&["sync", "--days", "7"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("0 updated, 0 added, 0 deleted, 1 unknown"));
+    let entries = history();
+    assert_eq!(entries["entries"].as_array().unwrap().len(), 3);
+    assert_eq!(entries["entries"][2]["status"], "unknown");
+
+    // A deleted letter may be sent again
+    let output = lxp.run(&["set", "letter2.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+fn job_track() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-job-track", &server);
+    lxp.dir.write_pdf("work/letter.pdf", 1);
+    let output = lxp.run(&["set", "letter.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let id = server.state().jobs_with_status("queue")[0].id.to_string();
+
+    let child = lxp
+        .command(&["job", "--track", &id, "--interval", "1"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(1500));
+    server.state().jobs.values_mut().for_each(|job| job.status = "sent".into());
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains(&format!("Job {} letter.pdf: queue", id)), "{}", out);
+    assert!(out.contains("queue -> sent"), "{}", out);
+    assert!(out.contains("All 1 job(s) sent"), "{}", out);
+
+    // Without ids the open jobs of the history are tracked, the sent one is done
+    let hold = server.state().add_job("portal.pdf", "hold");
+    lxp.run(&["sync"]);
+    let output = lxp.run(&["job", "--track", "--timeout", "1"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stdout(&output));
+    assert!(stdout(&output).contains(&format!("Still on hold for lack of credit: {}", hold)));
+
+    server.state().jobs.remove(&hold);
+    let output = lxp.run(&["job", "--track", &hold.to_string()]);
+    assert_eq!(output.status.code(), Some(2), "{}", stdout(&output));
+    assert!(stdout(&output).contains(&format!("Deleted job(s): {}", hold)));
+
+    // set tracks only the letters it uploaded
+    lxp.dir.write_pdf("work/other.pdf", 2);
+    let output = lxp.run(&["set", "other.pdf", "--track", "--interval", "1", "--timeout", "1"]);
+    assert_eq!(output.status.code(), Some(4), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("Tracking 1 job(s)"), "{}", out);
+    assert!(out.contains("other.pdf: queue"), "{}", out);
+    assert!(out.contains("Not sent yet: 1 of 1 job(s)"), "{}", out);
+}
+
+#[test]
+fn job_overview() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-job-overview", &server);
+    {
+        let mut state = server.state();
+        state.add_job("queued.pdf", "queue");
+        state.add_job("held.pdf", "hold");
+        state.add_job("sent.pdf", "sent");
+    }
+
+    let output = lxp.run(&["job", "-o"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("Credit balance 98.14 €"));
+    let soon = out.find("sent soon").unwrap();
+    let hold = out.find("credit exhausted").unwrap();
+    let sent = out.find("sent in the last 7 days").unwrap();
+    let queued = out.find("queued.pdf").unwrap();
+    let held = out.find("held.pdf").unwrap();
+    let done = out.find("sent.pdf").unwrap();
+    assert!(soon < queued && queued < hold);
+    assert!(hold < held && held < sent);
+    assert!(sent < done);
+    assert!(out.contains("The sum of the costs is 0.93 €"));
+}
+
+#[test]
+fn job_overview_with_server_error() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-job-error", &server);
+    for _ in 0..4 {
+        server.inject(Fault::MalformedJson);
+    }
+
+    let output = lxp.run(&["job", "-o"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Unexpected answer from web service"));
+}
+
+#[test]
+fn job_show() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-job-show", &server);
+    let id = server.state().add_job("letter1.pdf", "sent");
+
+    let output = lxp.run(&["job", "--show", &id.to_string()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("Address        letter1.pdf"));
+    assert!(out.contains("Status         sent"));
+    assert!(out.contains("Parent         -"));
+    assert!(out.contains("Cover          0"));
+    assert!(out.contains("Dispatch date  -"));
+    assert!(out.contains("Sent date      2020-12-11"));
+
+    let output = lxp.run(&["job", "-s", "4711"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Job Id 4711 not found"));
+}
+
+#[test]
+fn job_update() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-job-update", &server);
+    lxp.dir.write_pdf("work/letter1.pdf", 2);
+    let output = lxp.run(&["set", "letter1.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let id = server.state().jobs_with_status("queue")[0].id;
+    let archive = lxp.dir.path.join("config/archive/test");
+    assert!(archive.join(format!("{}.pdf", id)).is_file());
+
+    let output = lxp.run(&["job", "--update", &id.to_string(), "--duplex"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains(&format!("Job {} letter1.pdf updated", id)));
+    let job = server.state().jobs[&id].clone();
+    assert_eq!((job.color, job.mode.as_str()), (4, "duplex"));
+
+    // The service can't update, the archived copy is uploaded again
+    server.state().update_endpoint = false;
+    let output = lxp.run(&["job", "-u", &id.to_string(), "-b"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let queue = server.state().jobs_with_status("queue");
+    assert_eq!(queue.len(), 1);
+    assert_ne!(queue[0].id, id);
+    assert_eq!((queue[0].color, queue[0].mode.as_str()), (1, "duplex"));
+    assert!(stdout(&output).contains(&format!("replaced by job {}", queue[0].id)));
+    assert!(archive.join(format!("{}.pdf", queue[0].id)).is_file());
+
+    // The job history follows the job and its options
+    let history: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(archive.join("ledger.json")).unwrap()).unwrap();
+    let entry = &history["entries"][0];
+    assert_eq!(entry["job_id"], queue[0].id);
+    assert_eq!((entry["color"].as_i64(), entry["mode"].as_str()), (Some(1), Some("duplex")));
+}
+
+#[test]
+fn job_delete_all() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-job-delete-all", &server);
+    {
+        let mut state = server.state();
+        state.add_job("letter1.pdf", "queue");
+        state.add_job("letter2.pdf", "queue");
+        state.add_job("letter3.pdf", "hold");
+        state.add_job("letter4.pdf", "sent");
+    }
+
+    let output = lxp.run(&["job", "-d", "-a"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("3 job(s) deleted"));
+    let state = server.state();
+    assert_eq!(state.jobs.len(), 1);
+    assert_eq!(state.jobs_with_status("sent").len(), 1);
+}
+
+#[test]
+fn job_delete_unknown_id() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-job-delete-id", &server);
+
+    let output = lxp.run(&["job", "-d", "-i", "4711"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Job Id 4711 not found"));
+}
+
+#[test]
+fn invoice_list() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-invoice-list", &server);
+
+    let output = lxp.run(&["invoice", "-l"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("2020-10-31  30711 149.98 €"));
+    assert!(out.contains("2019-01-31  11328  27.12 €"));
+}
+
+#[test]
+fn invoice_current_and_by_id() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-invoice-get", &server);
+
+    let output = lxp.run(&["invoice", "-c"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let pdf = std::fs::read(lxp.path("2020-10-31_test-invoice.pdf")).unwrap();
+    assert!(pdf.starts_with(b"%PDF-"));
+
+    let output = lxp.run(&["invoice", "-i", "11328"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(lxp.path("2019-01-31_test-invoice.pdf").is_file());
+}
+
+#[test]
+fn watch_dir() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-watch-dir", &server);
+    let watched = lxp.dir.path.join("watched");
+    std::fs::create_dir_all(watched.join("sent")).unwrap();
+
+    let mut child = lxp
+        .command(&["watch-dir", watched.to_str().unwrap()])
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_secs(1));
+    lxp.dir.write_pdf("watched/letter1.pdf", 2);
+
+    // The watcher debounces events for 10 seconds
+    let uploaded = wait_for(Duration::from_secs(30), || {
+        watched.join("sent/letter1.pdf").is_file()
+    });
+    child.kill().ok();
+    child.wait().ok();
+
+    assert!(uploaded);
+    let queue = server.state().jobs_with_status("queue");
+    assert_eq!(queue.len(), 1);
+    assert_eq!(queue[0].address, "letter1.pdf");
+    assert_eq!(queue[0].pages, 2);
+    assert!(std::fs::read_to_string(watched.join("lxp.log"))
+        .unwrap()
+        .contains("sent"));
+    assert_eq!(
+        std::fs::read_to_string(watched.join("lxp.mode")).unwrap(),
+        "production"
+    );
+}
+
+#[test]
+fn profile_test() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-profile-test", &server);
+
+    let output = lxp.run(&["profile", "--test"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("User           user"));
+    assert!(out.contains("Account        4711"));
+    assert!(out.contains("Credit balance 98.14"));
+    assert!(out.contains("Latency"));
+
+    server.inject(Fault::BadAuth);
+    let output = lxp.run(&["profile", "-t", "test"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("check user name and api key"));
+
+    let output = lxp.run(&["profile", "-t", "unknown"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Could not test profile 'unknown'"));
+}
+
+#[test]
+fn profile_new_is_verified() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-profile-new-verified", &server);
+    let url = format!("{}wrong/", server.url);
+
+    server.inject(Fault::NotFound);
+    let output = lxp.run(&["profile", "-n", "wrong", "user", &url, API_KEY, "--test"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("it usually ends with '/v1/'"));
+    let output = lxp.run(&["profile", "-o"]);
+    assert!(!stdout(&output).contains("wrong"));
+    assert!(stdout(&output).contains("Active profile 'test'"));
+
+    let output = lxp.run(&["profile", "-n", "second", "user", &server.url, API_KEY, "-t"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Active profile is set to 'second'"));
+}
+
+#[test]
+fn sandbox_profile() {
+    let server = MockServer::start();
+    let sandbox = MockServer::start();
+    let lxp = Lxp::new("cli-sandbox-profile", &server);
+    let output = lxp.run(&["profile", "-n", "sb", USER, &server.url, API_KEY, "--sandbox"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    lxp.configure("sb", &format!("sandbox_url = \"{}\"", sandbox.url));
+    lxp.dir.write_pdf("work/letter1.pdf", 1);
+
+    let output = lxp.run(&["set", "letter1.pdf"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("[TEST]   Job letter1.pdf sent"));
+    assert_eq!(sandbox.state().jobs_with_status("queue").len(), 1);
+    assert!(server.state().jobs_with_status("queue").is_empty());
+
+    // Every line is marked, not only those after the first request
+    let output = lxp.run(&["job", "-o"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.starts_with("[TEST] lxp "), "{}", out);
+    assert!(out.contains("[TEST] Active profile 'sb'"));
+
+    let output = lxp.run(&["profile", "-o"]);
+    assert!(stdout(&output).contains(&format!("{} (sandbox)", sandbox.url)));
+}
+
+#[test]
+fn sandbox_option() {
+    let server = MockServer::start();
+    let sandbox = MockServer::start();
+    let lxp = Lxp::new("cli-sandbox-option", &server);
+    lxp.configure("test", &format!("sandbox_url = \"{}\"", sandbox.url));
+    lxp.dir.write_pdf("work/letter1.pdf", 1);
+
+    let output = lxp.run(&["set", "letter1.pdf", "--sandbox"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).lines().all(|line| line.starts_with("[TEST]")));
+    assert_eq!(sandbox.state().jobs_with_status("queue").len(), 1);
+    assert!(server.state().jobs_with_status("queue").is_empty());
+
+    let output = lxp.run(&["job", "-o"]);
+    assert!(!stdout(&output).contains("[TEST]"));
+    assert!(stdout(&output).contains("<No data>"));
+}
+
+#[test]
+fn watch_dir_refuses_mixed_profiles() {
+    let server = MockServer::start();
+    let sandbox = MockServer::start();
+    let lxp = Lxp::new("cli-watch-dir-mixed", &server);
+    lxp.configure("test", &format!("sandbox_url = \"{}\"", sandbox.url));
+    let watched = lxp.dir.path.join("watched");
+    std::fs::create_dir_all(&watched).unwrap();
+    std::fs::write(watched.join("lxp.mode"), "production").unwrap();
+
+    let output = lxp.run(&["--sandbox", "watch-dir", watched.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("refusing to watch it with a sandbox profile"));
+}
+
+#[test]
+fn watch_dir_split() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-watch-dir-split", &server);
+    let watched = lxp.dir.path.join("watched");
+    std::fs::create_dir_all(watched.join("sent")).unwrap();
+
+    let mut child = lxp
+        .command(&["watch-dir", watched.to_str().unwrap(), "--split_pages", "1"])
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_secs(1));
+    lxp.dir.write_pdf("watched/run.pdf", 3);
+
+    // The watcher debounces events for 10 seconds
+    let uploaded = wait_for(Duration::from_secs(30), || {
+        watched.join("sent/run.pdf").is_file()
+    });
+    child.kill().ok();
+    child.wait().ok();
+
+    assert!(uploaded);
+    assert_eq!(
+        queued_letters(&server),
+        vec![("run-001.pdf".into(), 1), ("run-002.pdf".into(), 1), ("run-003.pdf".into(), 1)]
+    );
+}
+
+#[test]
+fn watch_dir_merge() {
+    let server = MockServer::start();
+    let lxp = Lxp::new("cli-watch-dir-merge", &server);
+    let watched = lxp.dir.path.join("watched");
+    // A letter with the same name was sent before
+    std::fs::create_dir_all(watched.join("sent/letter")).unwrap();
+    lxp.dir.write_pdf("letter/2-terms.pdf", 2);
+    lxp.dir.write_pdf("letter/1-cover.pdf", 1);
+
+    let mut child = lxp
+        .command(&["watch-dir", watched.to_str().unwrap()])
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_secs(1));
+    std::fs::rename(lxp.dir.path.join("letter"), watched.join("letter")).unwrap();
+
+    // The watcher debounces events for 10 seconds
+    let uploaded = wait_for(Duration::from_secs(30), || {
+        watched.join("sent/letter-1/1-cover.pdf").is_file()
+    });
+    let running = child.try_wait().unwrap().is_none();
+    child.kill().ok();
+    child.wait().ok();
+
+    assert!(uploaded);
+    assert!(running);
+    assert_eq!(queued_letters(&server), vec![("1-cover.pdf".into(), 3)]);
+}
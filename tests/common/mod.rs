@@ -0,0 +1,661 @@
+// In-process mock of the LetterXpress web service and helpers shared by the
+// integration tests. The server runs on its own thread and runtime, so it can
+// be used by async library tests as well as by tests of the lxp binary.
+#![allow(dead_code)]
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+pub const USER: &str = "user";
+pub const API_KEY: &str = "0123456789abcdef";
+
+/// Error injected into the next request
+#[derive(Debug, Clone)]
+pub enum Fault {
+    /// Answer with status 401 as if the api key was wrong
+    BadAuth,
+    /// Answer with status 404
+    NotFound,
+    /// Answer with a body that is no JSON
+    MalformedJson,
+    /// Answer with a bare HTTP error
+    HttpStatus(u16),
+    /// Delay the answer, then proceed normally
+    Slow(Duration),
+    /// Answer with the given JSON
+    Json(Value),
+}
+
+#[derive(Debug, Clone)]
+pub struct MockJob {
+    pub id: u32,
+    pub address: String,
+    pub status: String,
+    pub pages: u32,
+    pub color: i64,
+    pub mode: String,
+    pub shipping: String,
+    pub cost: String,
+    pub cost_vat: String,
+    pub date: String,
+    pub dispatchdate: Option<String>,
+    pub checksum: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MockInvoice {
+    pub id: u32,
+    pub date: String,
+    pub sum: String,
+    pub vat: String,
+}
+
+#[derive(Debug)]
+pub struct State {
+    pub balance: String,
+    /// Currency of the account, the web service names it with the balance
+    pub currency: String,
+    pub jobs: BTreeMap<u32, MockJob>,
+    pub invoices: Vec<MockInvoice>,
+    pub faults: VecDeque<Fault>,
+    /// "METHOD endpoint" of every request received
+    pub requests: Vec<String>,
+    /// Host and proxy credentials of the last request sent through a proxy
+    pub proxied: Option<(String, String)>,
+    /// Serve getJob/{id}, older servers only have the job lists
+    pub job_endpoint: bool,
+    /// Serve updateJob/{id}
+    pub update_endpoint: bool,
+    next_id: u32,
+}
+
+impl Default for State {
+    fn default() -> State {
+        State {
+            balance: "98.14".into(),
+            currency: "EUR".into(),
+            jobs: BTreeMap::new(),
+            invoices: vec![
+                MockInvoice {
+                    id: 11328,
+                    date: "2019-01-31".into(),
+                    sum: "22.79".into(),
+                    vat: "4.33".into(),
+                },
+                MockInvoice {
+                    id: 30711,
+                    date: "2020-10-31".into(),
+                    sum: "126.03".into(),
+                    vat: "23.95".into(),
+                },
+            ],
+            faults: VecDeque::new(),
+            requests: Vec::new(),
+            proxied: None,
+            job_endpoint: true,
+            update_endpoint: true,
+            next_id: 57451,
+        }
+    }
+}
+
+impl State {
+    /// Add a job as if it was uploaded before
+    pub fn add_job(&mut self, address: &str, status: &str) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.jobs.insert(
+            id,
+            MockJob {
+                id,
+                address: address.into(),
+                status: status.into(),
+                pages: 1,
+                color: 4,
+                mode: "simplex".into(),
+                shipping: "national".into(),
+                cost: "0.78".into(),
+                cost_vat: "0.15".into(),
+                date: "2020-12-10 09:45:31".into(),
+                dispatchdate: None,
+                checksum: String::new(),
+            },
+        );
+        id
+    }
+
+    /// Let the print service send all letters of the queue
+    pub fn send_queue(&mut self) {
+        for job in self.jobs.values_mut().filter(|job| job.status == "queue") {
+            job.status = "sent".into();
+        }
+    }
+
+    pub fn jobs_with_status(&self, status: &str) -> Vec<MockJob> {
+        self.jobs
+            .values()
+            .filter(|job| job.status == status)
+            .cloned()
+            .collect()
+    }
+
+    pub fn count_requests(&self, request: &str) -> usize {
+        self.requests.iter().filter(|r| r.as_str() == request).count()
+    }
+}
+
+pub struct MockServer {
+    pub url: String,
+    pub state: Arc<Mutex<State>>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl MockServer {
+    pub fn start() -> MockServer {
+        let state = Arc::new(Mutex::new(State::default()));
+        let (addr_tx, addr_rx) = mpsc::channel();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+        let server_state = state.clone();
+        let thread = thread::spawn(move || {
+            let mut rt = tokio::runtime::Builder::new()
+                .basic_scheduler()
+                .enable_all()
+                .build()
+                .unwrap();
+            rt.block_on(async move {
+                let make_svc = make_service_fn(move |_conn| {
+                    let state = server_state.clone();
+                    async move {
+                        Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req)))
+                    }
+                });
+                let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+                let server = Server::bind(&addr).serve(make_svc);
+                addr_tx.send(server.local_addr()).unwrap();
+                let graceful = server.with_graceful_shutdown(async {
+                    shutdown_rx.await.ok();
+                });
+                graceful.await.unwrap();
+            });
+        });
+
+        let addr = addr_rx.recv().unwrap();
+        MockServer {
+            url: format!("http://{}/v1/", addr),
+            state,
+            shutdown: Some(shutdown_tx),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn inject(&self, fault: Fault) {
+        self.state().faults.push_back(fault);
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+fn reply(http_status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(http_status)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn ok(mut body: Value) -> Response<Body> {
+    body["status"] = json!(200);
+    body["message"] = json!("OK");
+    reply(StatusCode::OK, body)
+}
+
+fn status(status: u16, message: &str) -> Response<Body> {
+    reply(
+        StatusCode::from_u16(status).unwrap(),
+        json!({ "status": status, "message": message }),
+    )
+}
+
+fn job_json(job: &MockJob) -> Value {
+    json!({
+        "jid": job.id.to_string(),
+        "address": job.address,
+        "parent": null,
+        "status": job.status,
+        "mode": job.mode,
+        "color": job.color.to_string(),
+        "cover": "0",
+        "shipping": job.shipping,
+        "pages": job.pages.to_string(),
+        "cost": job.cost,
+        "cost_vat": job.cost_vat,
+        "date": job.date,
+        "dispatchdate": job.dispatchdate,
+        "sentdate": if job.status == "sent" { json!("2020-12-11 14:02:10") } else { json!(null) },
+    })
+}
+
+fn jobs_json(state: &State, status: &str) -> Value {
+    let jobs: serde_json::Map<String, Value> = state
+        .jobs
+        .values()
+        .filter(|job| job.status == status)
+        .map(|job| (job.id.to_string(), job_json(job)))
+        .collect();
+    json!({ "jobs": jobs })
+}
+
+fn invoice_json(invoice: &MockInvoice, with_pdf: bool) -> Value {
+    let mut v = json!({
+        "iid": invoice.id.to_string(),
+        "invoicedate": invoice.date,
+        "sum": invoice.sum,
+        "vat": invoice.vat,
+    });
+    if with_pdf {
+        v["pdf_data"] = json!(base64::encode(pdf_document(1)));
+    }
+    v
+}
+
+fn cents(value: &str) -> i64 {
+    (value.parse::<f64>().unwrap() * 100.0).round() as i64
+}
+
+fn euro(cents: i64) -> String {
+    format!("{}.{:02}", cents / 100, cents % 100)
+}
+
+async fn handle(state: Arc<Mutex<State>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().trim_start_matches("/v1/").to_string();
+    // Requests through a proxy carry the absolute url
+    let proxied = req.uri().host().map(|host| {
+        let auth = req
+            .headers()
+            .get("proxy-authorization")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        (host.to_string(), auth.to_string())
+    });
+    let bytes = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+    let body: Value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+
+    let fault = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(format!("{} {}", method, path));
+        if proxied.is_some() {
+            state.proxied = proxied;
+        }
+        state.faults.pop_front()
+    };
+    match fault {
+        Some(Fault::BadAuth) => return Ok(status(401, "Unauthorized")),
+        Some(Fault::NotFound) => return Ok(status(404, "Not Found")),
+        Some(Fault::MalformedJson) => {
+            return Ok(Response::new(Body::from("<html>Internal error</html>")))
+        }
+        Some(Fault::HttpStatus(code)) => {
+            return Ok(Response::builder()
+                .status(StatusCode::from_u16(code).unwrap())
+                .body(Body::from("Service Unavailable"))
+                .unwrap())
+        }
+        Some(Fault::Slow(delay)) => tokio::time::delay_for(delay).await,
+        Some(Fault::Json(body)) => return Ok(reply(StatusCode::OK, body)),
+        None => (),
+    }
+
+    if body["auth"]["username"] != USER || body["auth"]["apikey"] != API_KEY {
+        return Ok(status(401, "Unauthorized"));
+    }
+
+    let mut state = state.lock().unwrap();
+    let segments: Vec<&str> = path.split('/').collect();
+    let response = match (&method, segments.as_slice()) {
+        (&Method::GET, ["getBalance"]) => ok(json!({
+            "auth": { "id": "4711", "user": USER, "status": "active" },
+            "balance": { "value": state.balance, "currency": state.currency },
+        })),
+        (&Method::GET, ["getJob", id]) if state.job_endpoint => {
+            match state.jobs.values().find(|job| job.id.to_string() == *id) {
+                Some(job) => ok(json!({ "job": job_json(job) })),
+                None => status(404, "Job not found"),
+            }
+        }
+        (&Method::PUT, ["updateJob", id]) if state.update_endpoint => {
+            let spec = &body["letter"]["specification"];
+            match state.jobs.values_mut().find(|job| job.id.to_string() == *id) {
+                Some(job) if job.status == "sent" => status(409, "Job already sent"),
+                Some(job) => {
+                    job.color = spec["color"].as_i64().unwrap_or(job.color);
+                    job.mode = spec["mode"].as_str().unwrap_or(&job.mode).to_string();
+                    job.shipping = spec["ship"].as_str().unwrap_or(&job.shipping).to_string();
+                    ok(json!({}))
+                }
+                None => status(404, "Job not found"),
+            }
+        }
+        (&Method::GET, ["getJobs", "hold"]) => ok(jobs_json(&state, "hold")),
+        (&Method::GET, ["getJobs", "queue", _days]) => ok(jobs_json(&state, "queue")),
+        (&Method::GET, ["getJobs", "sent", _days]) => ok(jobs_json(&state, "sent")),
+        (&Method::POST, ["setJob"]) => set_job(&mut state, &body),
+        (&Method::DELETE, ["deleteJob", id]) => match id.parse::<u32>() {
+            Ok(id) => match state.jobs.get(&id).map(|job| job.status.clone()) {
+                Some(ref s) if s == "sent" => status(409, "Job already sent"),
+                Some(_) => {
+                    state.jobs.remove(&id);
+                    ok(json!({}))
+                }
+                None => status(404, "Job not found"),
+            },
+            Err(_) => status(400, "Bad Request"),
+        },
+        (&Method::GET, ["listInvoices"]) => {
+            let invoices: serde_json::Map<String, Value> = state
+                .invoices
+                .iter()
+                .map(|invoice| (invoice.id.to_string(), invoice_json(invoice, false)))
+                .collect();
+            ok(json!({ "invoices": invoices }))
+        }
+        (&Method::GET, ["getInvoice"]) => match state.invoices.iter().max_by_key(|i| i.id) {
+            Some(invoice) => ok(json!({ "invoice": invoice_json(invoice, true) })),
+            None => status(404, "Invoice not found"),
+        },
+        (&Method::GET, ["getInvoice", id]) => {
+            match state.invoices.iter().find(|i| i.id.to_string() == *id) {
+                Some(invoice) => ok(json!({ "invoice": invoice_json(invoice, true) })),
+                None => status(404, "Invoice not found"),
+            }
+        }
+        _ => status(404, "Unknown endpoint"),
+    };
+    Ok(response)
+}
+
+fn set_job(state: &mut State, body: &Value) -> Response<Body> {
+    let letter = &body["letter"];
+    let base64_file = letter["base64_file"].as_str().unwrap_or_default();
+    let checksum = letter["base64_checksum"].as_str().unwrap_or_default();
+    if format!("{:x}", md5::compute(base64_file)) != checksum {
+        return status(400, "Checksum mismatch");
+    }
+    let pdf = match base64::decode(base64_file) {
+        Ok(pdf) => pdf,
+        Err(_) => return status(400, "Invalid base64 data"),
+    };
+    let pages = count_pages(&pdf);
+    if pages == 0 {
+        return status(400, "No valid PDF document");
+    }
+
+    let spec = &letter["specification"];
+    let color = spec["color"].as_i64().unwrap_or(1);
+    let page_cost = if color == 4 { 10 } else { 5 };
+    let cost = 68 + page_cost * pages as i64;
+    let cost_vat = (cost * 19 + 50) / 100;
+
+    let balance = cents(&state.balance);
+    let status = if balance >= cost + cost_vat {
+        state.balance = euro(balance - cost - cost_vat);
+        "queue"
+    } else {
+        "hold"
+    };
+
+    let id = state.next_id;
+    state.next_id += 1;
+    let job = MockJob {
+        id,
+        address: letter["address"].as_str().unwrap_or_default().into(),
+        status: status.into(),
+        pages,
+        color,
+        mode: spec["mode"].as_str().unwrap_or("simplex").into(),
+        shipping: spec["ship"].as_str().unwrap_or("national").into(),
+        cost: euro(cost),
+        cost_vat: euro(cost_vat),
+        date: "2020-12-10 09:45:31".into(),
+        dispatchdate: letter["dispatchdate"].as_str().map(String::from),
+        checksum: checksum.into(),
+    };
+    state.jobs.insert(id, job);
+    ok(json!({ "letter": { "job_id": id.to_string(), "status": status } }))
+}
+
+// Good enough for the documents created by pdf_document() and for the
+// parts written by lopdf
+fn count_pages(pdf: &[u8]) -> u32 {
+    if !pdf.starts_with(b"%PDF-") {
+        return 0;
+    }
+    let text = String::from_utf8_lossy(pdf).replace("/Type /", "/Type/");
+    (text.matches("/Type/Page").count() - text.matches("/Type/Pages").count()) as u32
+}
+
+/// A valid PDF document with the given number of empty A4 pages
+pub fn pdf_document(pages: usize) -> Vec<u8> {
+    pdf_document_with_trailer(pages, "")
+}
+
+/// A PDF document with a page for each text, empty texts give blank pages
+pub fn pdf_document_with_texts(texts: &[&str]) -> Vec<u8> {
+    let contents: Vec<String> = texts
+        .iter()
+        .map(|text| match text.is_empty() {
+            true => String::new(),
+            false => format!("BT /F1 12 Tf 72 712 Td ({}) Tj ET", text),
+        })
+        .collect();
+    pdf_document_with_contents(&contents)
+}
+
+/// A one page letter with the lines in the address window of DIN 5008
+/// form B, 25 mm from the left and starting 50 mm from the top
+pub fn pdf_letter(address: &[&str]) -> Vec<u8> {
+    let lines: Vec<String> = address.iter().map(|line| format!("({}) Tj T*", line)).collect();
+    let content = format!(
+        "BT /F1 10 Tf 12 TL 71 700 Td {} ET BT /F1 12 Tf 71 400 Td (Dear Sir or Madam) Tj ET",
+        lines.join(" ")
+    );
+    pdf_document_with_contents(&[content])
+}
+
+/// A PDF document with a page for each content stream
+pub fn pdf_document_with_contents(contents: &[String]) -> Vec<u8> {
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..contents.len())
+                .map(|i| format!("{} 0 R", 2 * i + 3))
+                .collect::<Vec<_>>()
+                .join(" "),
+            contents.len()
+        ),
+    ];
+    for (i, content) in contents.iter().enumerate() {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 595 842] /Contents {} 0 R \
+             /Resources << /Font << /F1 << /Type /Font /Subtype /Type1 /BaseFont /Helvetica >> >> >> >>",
+            2 * i + 4
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}\nendstream",
+            content.len(),
+            content
+        ));
+    }
+    write_pdf_objects(&objects, "")
+}
+
+/// A PDF document with additional entries in the trailer dictionary
+pub fn pdf_document_with_trailer(pages: usize, trailer: &str) -> Vec<u8> {
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..pages)
+                .map(|i| format!("{} 0 R", i + 3))
+                .collect::<Vec<_>>()
+                .join(" "),
+            pages
+        ),
+    ];
+    for _ in 0..pages {
+        objects.push("<< /Type /Page /Parent 2 0 R /MediaBox [0 0 595 842] >>".to_string());
+    }
+    write_pdf_objects(&objects, trailer)
+}
+
+// Numbers the objects from 1, the first one is the catalog
+fn write_pdf_objects(objects: &[String], trailer: &str) -> Vec<u8> {
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).bytes());
+    }
+    let xref = pdf.len();
+    pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).bytes());
+    for offset in offsets {
+        pdf.extend(format!("{:010} 00000 n \n", offset).bytes());
+    }
+    pdf.extend(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R {}>>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            trailer,
+            xref
+        )
+        .bytes(),
+    );
+    pdf
+}
+
+/// Scratch directory of a test, removed when dropped
+pub struct TempDir {
+    pub path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("lxp-{}-{}", name, std::process::id()));
+        std::fs::remove_dir_all(&path).ok();
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn write_pdf(&self, name: &str, pages: usize) -> PathBuf {
+        let path = self.path.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        // Letters with the same content would be duplicates
+        let id = format!("/ID [({0}) ({0})] ", name.replace('/', "-"));
+        std::fs::write(&path, pdf_document_with_trailer(pages, &id)).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.path).ok();
+    }
+}
+
+/// The lxp binary, configured with a profile pointing to a mock server
+pub struct Lxp {
+    pub dir: TempDir,
+}
+
+impl Lxp {
+    pub fn new(name: &str, server: &MockServer) -> Lxp {
+        let lxp = Lxp {
+            dir: TempDir::new(name),
+        };
+        let output = lxp.run(&["profile", "-n", "test", USER, &server.url, API_KEY]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        lxp
+    }
+
+    pub fn config_dir(&self) -> PathBuf {
+        self.dir.path.join("config")
+    }
+
+    /// Add a setting to a profile in the config file
+    pub fn configure(&self, profile: &str, setting: &str) {
+        let path = self.config_dir().join("lxp.toml");
+        let header = format!("[profiles.{}]\n", profile);
+        let config = std::fs::read_to_string(&path).unwrap();
+        assert!(config.contains(&header), "{}", config);
+        let config = config.replace(&header, &format!("{}{}\n", header, setting));
+        std::fs::write(&path, config).unwrap();
+    }
+
+    pub fn work_dir(&self) -> PathBuf {
+        self.dir.path.join("work")
+    }
+
+    pub fn command(&self, args: &[&str]) -> Command {
+        std::fs::create_dir_all(self.work_dir()).unwrap();
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_lxp"));
+        cmd.args(args)
+            .current_dir(self.work_dir())
+            .env("LXP_CONFIG_DIR", self.config_dir());
+        cmd
+    }
+
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.work_dir().join(name)
+    }
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+pub fn wait_for<F: Fn() -> bool>(timeout: Duration, f: F) -> bool {
+    let step = Duration::from_millis(100);
+    let mut waited = Duration::from_secs(0);
+    while waited < timeout {
+        if f() {
+            return true;
+        }
+        thread::sleep(step);
+        waited += step;
+    }
+    f()
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use common::{pdf_document, pdf_document_with_texts, pdf_document_with_trailer, pdf_letter};
+use lxpapi::{merge_pdfs, read_address, split_pdf, validate_pdf, AddressProblem, AddressWindow};
+use lxpapi::{MergeError, PdfError, SplitError, SplitRule};
+
+#[test]
+fn valid_document() {
+    let info = validate_pdf(&pdf_document(3)).unwrap();
+    assert_eq!(info.pages, 3);
+    assert_eq!(info.version, "1.4");
+}
+
+#[test]
+fn other_file_type() {
+    let docx = b"PK\x03\x04[Content_Types].xml".to_vec();
+    assert_eq!(validate_pdf(&docx), Err(PdfError::NoHeader));
+    assert_eq!(validate_pdf(b""), Err(PdfError::NoHeader));
+}
+
+#[test]
+fn truncated_download() {
+    let pdf = pdf_document(2);
+    assert_eq!(validate_pdf(&pdf[..pdf.len() / 2]), Err(PdfError::NoEndMarker));
+}
+
+#[test]
+fn damaged_structure() {
+    let pdf = b"%PDF-1.4\nthis is garbage\n%%EOF\n".to_vec();
+    match validate_pdf(&pdf) {
+        Err(PdfError::Unparsable(_)) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn encrypted_document() {
+    let pdf = pdf_document_with_trailer(
+        1,
+        "/Encrypt << /Filter /Standard /V 1 /R 2 /O (owner) /U (user) /P -4 >> ",
+    );
+    assert_eq!(validate_pdf(&pdf), Err(PdfError::Encrypted));
+}
+
+#[test]
+fn document_without_pages() {
+    assert_eq!(validate_pdf(&pdf_document(0)), Err(PdfError::NoPages));
+}
+
+fn page_counts(pdf: &[u8], rule: &SplitRule) -> Vec<u32> {
+    split_pdf(pdf, rule)
+        .unwrap()
+        .iter()
+        .map(|part| validate_pdf(&part.pdf).unwrap().pages)
+        .collect()
+}
+
+#[test]
+fn split_by_page_count() {
+    let pdf = pdf_document(7);
+    assert_eq!(page_counts(&pdf, &SplitRule::Pages(3)), vec![3, 3, 1]);
+    let parts = split_pdf(&pdf, &SplitRule::Pages(3)).unwrap();
+    assert_eq!(parts[1].pages, 4..=6);
+}
+
+#[test]
+fn split_by_page_ranges() {
+    let pdf = pdf_document(6);
+    let rule = SplitRule::Ranges(vec![1..=2, 3..=3, 5..=6]);
+    assert_eq!(page_counts(&pdf, &rule), vec![2, 1, 2]);
+
+    let rule = SplitRule::Ranges(vec![1..=2, 5..=7]);
+    assert_eq!(
+        split_pdf(&pdf, &rule),
+        Err(SplitError::Range {
+            range: 5..=7,
+            pages: 6
+        })
+    );
+}
+
+#[test]
+fn split_parts_only_hold_their_pages() {
+    let single = split_pdf(&pdf_document(1), &SplitRule::Pages(1)).unwrap();
+    let parts = split_pdf(&pdf_document(200), &SplitRule::Pages(1)).unwrap();
+    assert_eq!(parts.len(), 200);
+    let objects = |pdf: &[u8]| String::from_utf8_lossy(pdf).matches(" obj").count();
+    assert!(parts.iter().all(|part| objects(&part.pdf) == objects(&single[0].pdf)));
+}
+
+#[test]
+fn split_at_separator_text() {
+    let pdf = pdf_document_with_texts(&[
+        "Letter A", "page 2", "--- NEXT LETTER ---", "Letter B", "--- NEXT LETTER ---",
+    ]);
+    let rule = SplitRule::Separator("NEXT LETTER".into());
+    assert_eq!(page_counts(&pdf, &rule), vec![2, 1]);
+    let parts = split_pdf(&pdf, &rule).unwrap();
+    assert!(String::from_utf8_lossy(&parts[1].pdf).contains("Letter B"));
+    assert!(!String::from_utf8_lossy(&parts[1].pdf).contains("Letter A"));
+}
+
+#[test]
+fn split_at_blank_pages() {
+    let pdf = pdf_document_with_texts(&["Letter A", "", "Letter B", "page 2", "", ""]);
+    assert_eq!(page_counts(&pdf, &SplitRule::BlankPage), vec![1, 2]);
+
+    let pdf = pdf_document(3);
+    assert_eq!(split_pdf(&pdf, &SplitRule::BlankPage), Err(SplitError::NoLetters));
+}
+
+#[test]
+fn merge_documents() {
+    let cover = pdf_document_with_texts(&["Cover letter"]);
+    let contract = pdf_document(3);
+    let terms = pdf_document_with_texts(&["Terms", "and conditions"]);
+    let merged = merge_pdfs(&[&cover, &contract, &terms]).unwrap();
+    assert_eq!(validate_pdf(&merged).unwrap().pages, 6);
+
+    // The cover letter comes first, the terms last
+    let parts = split_pdf(&merged, &SplitRule::Ranges(vec![1..=1, 6..=6])).unwrap();
+    assert!(String::from_utf8_lossy(&parts[0].pdf).contains("Cover letter"));
+    assert!(String::from_utf8_lossy(&parts[1].pdf).contains("and conditions"));
+}
+
+#[test]
+fn merge_invalid_documents() {
+    assert_eq!(merge_pdfs(&[]), Err(MergeError::NoDocuments));
+    let text = b"Dear Sir or Madam".to_vec();
+    assert_eq!(
+        merge_pdfs(&[&pdf_document(1), &text]),
+        Err(MergeError::Pdf {
+            index: 1,
+            reason: PdfError::NoHeader
+        })
+    );
+}
+
+#[test]
+fn address_window() {
+    let address = ["Max Mustermann", "Musterstr. 1", "12345 Berlin"];
+    let found = read_address(&pdf_letter(&address), &AddressWindow::din5008_b()).unwrap();
+    assert_eq!(found.lines, address);
+    assert_eq!(found.problem, None);
+
+    // The salutation is far below the window
+    let lower = AddressWindow {
+        top: 180.0,
+        ..AddressWindow::din5008_b()
+    };
+    let found = read_address(&pdf_letter(&address), &lower).unwrap();
+    assert!(found.lines.is_empty());
+    assert_eq!(found.problem, Some(AddressProblem::Empty));
+
+    let found = read_address(&pdf_document(1), &AddressWindow::din5008_b()).unwrap();
+    assert_eq!(found.problem, Some(AddressProblem::Empty));
+}
+
+#[test]
+fn address_window_overflow() {
+    let long = "Gesellschaft fuer angewandte Verwaltungsinformatik mbH & Co. KG";
+    let found = read_address(&pdf_letter(&[long, "12345 Berlin"]), &AddressWindow::default()).unwrap();
+    assert_eq!(found.problem, Some(AddressProblem::Overflow { text: long.into() }));
+
+    let lines = ["line"; 10];
+    let found = read_address(&pdf_letter(&lines), &AddressWindow::default()).unwrap();
+    assert_eq!(found.problem, Some(AddressProblem::TooManyLines { lines: 10 }));
+}